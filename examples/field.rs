@@ -18,12 +18,16 @@ mod app {
     };
 
     use libdaisy::{
-        field::{Field, FieldKeyboard, FieldLeds, FieldSwitches, FIELD_DISPLAY_SIZE},
+        field::{
+            Field, FieldControlPins, FieldControls, FieldCvOutPins, FieldKeyboard, FieldLeds,
+            FieldSwitches, FIELD_DISPLAY_SIZE, FIELD_KEY_COUNT, FIELD_KNOB_COUNT,
+        },
         gpio, logger,
         prelude::*,
         system::System,
     };
     use stm32h7xx_hal::{
+        adc,
         delay::Delay,
         hal::digital::v2::InputPin,
         stm32,
@@ -39,6 +43,7 @@ mod app {
         field_leds: FieldLeds,
         keyboard: FieldKeyboard,
         switches: FieldSwitches,
+        controls: FieldControls,
         timer2: Timer<stm32::TIM2>,
     }
 
@@ -100,6 +105,13 @@ mod app {
         );
 
         let mut delay = Delay::new(ctx.core.SYST, ccdr.clocks);
+        let (adc1, _adc2) = adc::adc12(
+            device.ADC1,
+            device.ADC2,
+            &mut delay,
+            ccdr.peripheral.ADC12,
+            &ccdr.clocks,
+        );
 
         let mut field = Field::new(
             //leds
@@ -117,6 +129,29 @@ mod app {
             //gates
             gpio.daisy0.take().unwrap(),
             gpio.daisy15.take().unwrap(),
+            //cv outputs
+            FieldCvOutPins {
+                dac: device.DAC,
+                dac_rec: ccdr.peripheral.DAC12,
+                out1: gpio.daisy23.take().unwrap(),
+                out2: gpio.daisy22.take().unwrap(),
+            },
+            //knobs and cv inputs
+            FieldControlPins {
+                adc: adc1,
+                knob_mux: gpio.daisy16.take().unwrap(),
+                mux_select: (
+                    gpio.daisy21.take().unwrap(),
+                    gpio.daisy20.take().unwrap(),
+                    gpio.daisy19.take().unwrap(),
+                ),
+                cv_in: (
+                    gpio.daisy17.take().unwrap(),
+                    gpio.daisy18.take().unwrap(),
+                    gpio.daisy25.take().unwrap(),
+                    gpio.daisy24.take().unwrap(),
+                ),
+            },
             //midi
             gpio.daisy13.take().unwrap(),
             gpio.daisy14.take().unwrap(),
@@ -137,6 +172,7 @@ mod app {
         let leds = field.split_leds();
         let keyboard = field.split_keyboard();
         let switches = field.split_switches();
+        let controls = field.split_controls();
 
         let mut disp = field.split_display();
 
@@ -172,6 +208,7 @@ mod app {
                 timer2,
                 keyboard,
                 switches,
+                controls,
                 field_leds: leds,
             },
             init::Monotonics(),
@@ -181,32 +218,40 @@ mod app {
     #[task(
         binds = TIM2,
         local = [
-        timer2, seed_led, field_leds, keyboard, switches,
+        timer2, seed_led, field_leds, keyboard, switches, controls,
         led_is_on: bool = true, brightness: u8 = 0
         ]
     )]
     fn blink(ctx: blink::Context) {
         ctx.local.timer2.clear_irq();
 
-        let r = ctx.local.keyboard.read();
+        ctx.local.keyboard.update();
 
-        for by in 0..2 {
-            let byte = r[by];
-            for b in 0..8 {
-                ctx.local
-                    .field_leds
-                    .button_set(by * 8 + b, if byte & (1 << b) != 0 { 0xFF } else { 0 });
-            }
+        for i in 0..FIELD_KEY_COUNT {
+            let pressed = ctx.local.keyboard.is_pressed(i);
+            ctx.local
+                .field_leds
+                .button_set(i, if pressed { 0xFF } else { 0 });
         }
 
-        ctx.local.field_leds.pot_set_all(*ctx.local.brightness);
+        //the pot leds follow the knobs, scaled by the brightness
+        ctx.local.controls.update();
+        for i in 0..FIELD_KNOB_COUNT {
+            let value = ctx.local.controls.knob(i).get_value();
+            ctx.local
+                .field_leds
+                .pot_set(i, (value * *ctx.local.brightness as f32) as u8);
+        }
         if *ctx.local.led_is_on {
             ctx.local.seed_led.set_high().unwrap();
         } else {
             ctx.local.seed_led.set_low().unwrap();
         }
         *ctx.local.led_is_on = !(*ctx.local.led_is_on);
-        ctx.local.field_leds.draw();
+        //a glitch on the LED board shouldn't stop the instrument
+        if let Err(e) = ctx.local.field_leds.draw() {
+            info!("LED error: {:?}", e);
+        }
 
         match ctx.local.switches.0.is_low() {
             Ok(true) => {
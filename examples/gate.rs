@@ -0,0 +1,96 @@
+//! examples/gate.rs
+#![no_main]
+#![no_std]
+
+#[rtic::app(
+    device = stm32h7xx_hal::stm32,
+    peripherals = true,
+)]
+mod app {
+    use log::info;
+    // Includes a panic handler and optional logging facilities
+    use libdaisy::logger;
+
+    use libdaisy::audio;
+    use libdaisy::gate::{GateIn, GateOut};
+    use libdaisy::gpio::*;
+    use libdaisy::prelude::*;
+    use libdaisy::system;
+    use stm32h7xx_hal::gpio::Floating;
+
+    #[shared]
+    struct Shared {
+        gate_out: GateOut<Daisy15<Output<PushPull>>>,
+    }
+
+    #[local]
+    struct Local {
+        audio: audio::Audio,
+        buffer: audio::AudioBuffer,
+        gate_in: GateIn<Daisy0<Input<Floating>>>,
+    }
+
+    #[init]
+    fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
+        logger::init();
+        let mut system = system::System::init(ctx.core, ctx.device);
+        let buffer = [(0.0, 0.0); audio::BLOCK_SIZE_MAX];
+
+        // The gate input circuit inverts
+        let daisy0 = system.gpio.daisy0.take().unwrap().into_floating_input();
+        let gate_in = GateIn::new(daisy0, true, &mut system.syscfg, &mut system.exti);
+
+        let daisy15 = system.gpio.daisy15.take().unwrap().into_push_pull_output();
+        let mut gate_out = GateOut::new(daisy15, false);
+        gate_out.set_trigger_ms(10);
+
+        (
+            Shared { gate_out },
+            Local {
+                audio: system.audio,
+                buffer,
+                gate_in,
+            },
+            init::Monotonics(),
+        )
+    }
+
+    #[idle]
+    fn idle(_ctx: idle::Context) -> ! {
+        loop {
+            cortex_m::asm::nop();
+        }
+    }
+
+    // Echo every incoming clock pulse as a trigger
+    #[task(binds = EXTI15_10, shared = [gate_out], local = [gate_in], priority = 4)]
+    fn gate_handler(mut ctx: gate_handler::Context) {
+        let gate_in = ctx.local.gate_in;
+        gate_in.handle_interrupt();
+
+        if gate_in.take_trigger() {
+            ctx.shared.gate_out.lock(|gate_out| gate_out.trigger());
+            if let Some(bpm) = gate_in.bpm(4) {
+                info!("{} BPM", bpm);
+            }
+        }
+    }
+
+    // The trigger length is timed in samples by the audio callback
+    #[task(binds = DMA1_STR1, shared = [gate_out], local = [audio, buffer], priority = 8)]
+    fn audio_handler(mut ctx: audio_handler::Context) {
+        let audio = ctx.local.audio;
+        let buffer = ctx.local.buffer;
+
+        if audio.get_stereo(buffer) {
+            for (left, right) in buffer {
+                audio.push_stereo((*left, *right)).unwrap();
+            }
+            ctx.shared
+                .gate_out
+                .lock(|gate_out| gate_out.advance(audio::BLOCK_SIZE_MAX as u32));
+        } else {
+            info!("Error reading data!");
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! examples/hid_events.rs
+#![no_main]
+#![no_std]
+
+#[rtic::app(
+    device = stm32h7xx_hal::stm32,
+    peripherals = true,
+    dispatchers = [EXTI0],
+)]
+mod app {
+    use log::info;
+    // Includes a panic handler and optional logging facilities
+    use libdaisy::logger;
+
+    use stm32h7xx_hal::stm32;
+    use stm32h7xx_hal::timer::Timer;
+
+    use libdaisy::gpio::*;
+    use libdaisy::hid::{self, EventSource, HidEvent};
+    use libdaisy::prelude::*;
+    use libdaisy::system;
+
+    const QUEUE_SIZE: usize = 32;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        seed_led: SeedLed,
+        switch1: hid::Switch<Daisy28<Input<PullUp>>>,
+        encoder1: hid::Encoder<Daisy26<Input<PullUp>>, Daisy27<Input<PullUp>>>,
+        producer: hid::EventProducer<'static, QUEUE_SIZE>,
+        consumer: hid::EventConsumer<'static, QUEUE_SIZE>,
+        timer2: Timer<stm32::TIM2>,
+    }
+
+    #[init(local = [queue: hid::EventQueue<QUEUE_SIZE> = hid::EventQueue::new()])]
+    fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
+        logger::init();
+        let mut system = system::System::init(ctx.core, ctx.device);
+
+        system.timer2.set_freq(1.ms());
+
+        let daisy26 = system.gpio.daisy26.take().unwrap().into_pull_up_input();
+        let daisy27 = system.gpio.daisy27.take().unwrap().into_pull_up_input();
+        let daisy28 = system.gpio.daisy28.take().unwrap().into_pull_up_input();
+
+        let mut switch1 = hid::Switch::new(daisy28, hid::SwitchType::PullUp);
+        switch1.set_double_time(Some(500));
+        switch1.set_held_time(Some(1000));
+
+        let encoder1 = hid::Encoder::new(daisy26, daisy27);
+
+        let (producer, consumer) = ctx.local.queue.split();
+
+        (
+            Shared {},
+            Local {
+                seed_led: system.gpio.led,
+                switch1,
+                encoder1,
+                producer,
+                consumer,
+                timer2: system.timer2,
+            },
+            init::Monotonics(),
+        )
+    }
+
+    #[idle]
+    fn idle(_ctx: idle::Context) -> ! {
+        loop {
+            cortex_m::asm::nop();
+        }
+    }
+
+    // Scan the controls at a high priority
+    #[task(binds = TIM2, priority = 2, local = [timer2, switch1, encoder1, producer])]
+    fn scan(ctx: scan::Context) {
+        ctx.local.timer2.clear_irq();
+
+        ctx.local.switch1.update();
+        ctx.local.encoder1.update();
+
+        let producer = ctx.local.producer;
+        ctx.local.switch1.push_events(0, producer);
+        ctx.local.encoder1.push_events(1, producer);
+
+        if producer.len() > 0 {
+            ui::spawn().ok();
+        }
+    }
+
+    // Handle the events at a lower priority without missing any
+    #[task(priority = 1, local = [consumer, seed_led, led_is_on: bool = false])]
+    fn ui(ctx: ui::Context) {
+        while let Some(event) = ctx.local.consumer.dequeue() {
+            match event {
+                HidEvent::Pressed(_) => *ctx.local.led_is_on = !*ctx.local.led_is_on,
+                HidEvent::DoublePress(_) => info!("Button pressed twice!"),
+                HidEvent::LongPress(_) => info!("Button held!"),
+                HidEvent::Encoder(_, inc) => info!("Encoder {}", inc),
+                _ => (),
+            }
+        }
+
+        if *ctx.local.led_is_on {
+            ctx.local.seed_led.set_high().unwrap();
+        } else {
+            ctx.local.seed_led.set_low().unwrap();
+        }
+    }
+}
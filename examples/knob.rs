@@ -62,7 +62,13 @@ mod app {
             .expect("Failed to get pin 21!")
             .into_analog();
 
-        let control1 = hid::AnalogControl::new(daisy21, adc1_max);
+        let mut control1 = hid::AnalogControl::new(daisy21, adc1_max);
+        // Smooth out noise and ignore jitter smaller than the deadband
+        control1.set_filter(hid::AnalogFilter::one_pole(
+            10.0,
+            Hertz(duty_cycle * resolution),
+        ));
+        control1.set_deadband(0.002);
 
         (
             Shared {},
@@ -95,7 +101,9 @@ mod app {
         }
 
         led1.set_brightness(control1.get_value());
-        info!("{}", control1.get_value());
+        if let Some(value) = control1.read_changed() {
+            info!("{}", value);
+        }
         led1.update();
     }
 }
@@ -0,0 +1,96 @@
+//! examples/rgb_led.rs
+#![no_main]
+#![no_std]
+
+#[rtic::app(
+    device = stm32h7xx_hal::stm32,
+    peripherals = true,
+)]
+mod app {
+    // Includes a panic handler and optional logging facilities
+    use libdaisy::logger;
+
+    use stm32h7xx_hal::stm32;
+    use stm32h7xx_hal::timer::Timer;
+
+    use libdaisy::gpio::*;
+    use libdaisy::hid;
+    use libdaisy::prelude::*;
+    use libdaisy::system;
+    use stm32h7xx_hal::time::Hertz;
+
+    type RgbLed = hid::RgbLed<
+        hid::Led<Daisy20<Output<PushPull>>>,
+        hid::Led<Daisy19<Output<PushPull>>>,
+        hid::Led<Daisy18<Output<PushPull>>>,
+    >;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        led1: RgbLed,
+        timer2: Timer<stm32::TIM2>,
+    }
+
+    #[init]
+    fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
+        logger::init();
+        let mut system = system::System::init(ctx.core, ctx.device);
+
+        let duty_cycle = 50;
+        let resolution = 20;
+
+        system.timer2.set_freq(Hertz(duty_cycle * resolution));
+
+        // Common anode LEDs are on when the pin is low
+        let red = hid::Led::new(
+            system.gpio.daisy20.take().unwrap().into_push_pull_output(),
+            true,
+            resolution,
+        );
+        let green = hid::Led::new(
+            system.gpio.daisy19.take().unwrap().into_push_pull_output(),
+            true,
+            resolution,
+        );
+        let blue = hid::Led::new(
+            system.gpio.daisy18.take().unwrap().into_push_pull_output(),
+            true,
+            resolution,
+        );
+
+        let led1 = hid::RgbLed::new(red, green, blue, duty_cycle * resolution);
+
+        (
+            Shared {},
+            Local {
+                led1,
+                timer2: system.timer2,
+            },
+            init::Monotonics(),
+        )
+    }
+
+    #[idle]
+    fn idle(_cx: idle::Context) -> ! {
+        loop {
+            cortex_m::asm::nop();
+        }
+    }
+
+    #[task(binds = TIM2, local = [timer2, led1, hue: f32 = 0.0])]
+    fn interface_handler(ctx: interface_handler::Context) {
+        ctx.local.timer2.clear_irq();
+
+        // Cycle through the color wheel every 10 seconds
+        *ctx.local.hue += 1.0 / 10_000.0;
+        if *ctx.local.hue > 1.0 {
+            *ctx.local.hue -= 1.0;
+        }
+
+        ctx.local.led1.set_hsv(*ctx.local.hue, 1.0, 1.0);
+        ctx.local.led1.update();
+    }
+}
@@ -18,6 +18,8 @@ mod app {
     use libdaisy::hid;
     use libdaisy::prelude::*;
     use libdaisy::system;
+    use stm32h7xx_hal::time::Hertz;
+
     #[shared]
     struct Shared {}
 
@@ -42,10 +44,12 @@ mod app {
 
         system.timer2.set_freq(1.ms());
 
-        // Switch rate is determined by timer freq
+        // Thresholds are in milliseconds relative to the timer freq
         let mut switch1 = hid::Switch::new(daisy28, hid::SwitchType::PullUp);
-        switch1.set_double_thresh(Some(500));
-        switch1.set_held_thresh(Some(1500));
+        switch1.set_update_rate(Hertz(1_000));
+        switch1.set_double_time(Some(500));
+        switch1.set_held_time(Some(1500));
+        switch1.set_repeat_time(Some((500, 250)));
 
         (
             Shared {},
@@ -71,8 +75,16 @@ mod app {
         let switch1 = ctx.local.switch1;
         switch1.update();
 
-        if switch1.is_held() {
+        if switch1.is_long_press() {
             info!("Button held!");
+        }
+
+        if switch1.is_repeat() {
+            info!("Button repeat!");
+        }
+
+        if switch1.is_long_release() {
+            info!("Button released after hold!");
             *ctx.local.led_is_on = false;
         }
 
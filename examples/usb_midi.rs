@@ -10,37 +10,21 @@
     peripherals = true,
 )]
 mod app {
-    use libdaisy::{gpio, prelude::*, system::System};
+    use libdaisy::{
+        gpio,
+        midi::MidiEvent,
+        prelude::*,
+        system::System,
+        usb_midi::{self, UsbMidi},
+    };
     use stm32h7xx_hal::{
-        rcc::rec::UsbClkSel,
         stm32,
         timer::{Event, Timer},
-        usb_hs::{UsbBus, USB2},
-    };
-
-    use num_enum::TryFromPrimitive;
-    use usb_device::prelude::*;
-    use usbd_midi::{
-        data::{
-            byte::{from_traits::FromClamped, u7::U7},
-            midi::{channel::Channel as MidiChannel, message::Message, notes::Note},
-            usb::constants::{USB_AUDIO_CLASS, USB_MIDISTREAMING_SUBCLASS},
-            usb_midi::{
-                midi_packet_reader::MidiPacketBufferReader,
-                usb_midi_event_packet::UsbMidiEventPacket,
-            },
-        },
-        midi_device::MidiClass,
     };
 
-    static mut EP_MEMORY: [u32; 1024] = [0; 1024];
-
     #[shared]
     struct Shared {
-        usb: (
-            UsbDevice<'static, UsbBus<USB2>>,
-            MidiClass<'static, UsbBus<USB2>>,
-        ),
+        usb: UsbMidi,
     }
 
     #[local]
@@ -53,8 +37,7 @@ mod app {
     fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
         let device = ctx.device;
         let mut ccdr = System::init_clocks(device.PWR, device.RCC, &device.SYSCFG);
-        let _ = ccdr.clocks.hsi48_ck().expect("HSI48 must run");
-        ccdr.peripheral.kernel_usb_clk_mux(UsbClkSel::HSI48);
+        usb_midi::enable_clock(&mut ccdr);
 
         /*
         unsafe {
@@ -111,42 +94,20 @@ mod app {
             Some(gpiob.pb15),
         );
 
-        let (pin_dm, pin_dp) = {
-            (
-                gpioa.pa11.into_alternate_af10(),
-                gpioa.pa12.into_alternate_af10(),
-            )
-        };
-        //float makes this a device
-        gpioa.pa10.into_floating_input();
-        let usb = USB2::new(
+        let usb = UsbMidi::new(
             device.OTG2_HS_GLOBAL,
             device.OTG2_HS_DEVICE,
             device.OTG2_HS_PWRCLK,
-            pin_dm,
-            pin_dp,
+            gpioa.pa10,
+            gpioa.pa11,
+            gpioa.pa12,
             ccdr.peripheral.USB2OTG,
             &ccdr.clocks,
+            "daisy midi",
         );
 
-        let usb_bus = cortex_m::singleton!(
-            : usb_device::class_prelude::UsbBusAllocator<UsbBus<USB2>> =
-                UsbBus::new(usb, unsafe { &mut EP_MEMORY })
-        )
-        .unwrap();
-
-        let midi = MidiClass::new(usb_bus, 1, 1).unwrap();
-
-        let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x5e4))
-            .product("daisy midi")
-            .device_class(USB_AUDIO_CLASS)
-            .device_sub_class(USB_MIDISTREAMING_SUBCLASS)
-            .build();
-
         (
-            Shared {
-                usb: (usb_dev, midi),
-            },
+            Shared { usb },
             Local {
                 seed_led: gpio.led,
                 timer2,
@@ -170,24 +131,26 @@ mod app {
     fn send_note(mut ctx: send_note::Context) {
         ctx.local.timer2.clear_irq();
         let (local, shared) = (&mut ctx.local, &mut ctx.shared);
-        shared.usb.lock(|(_usb_dev, midi)| {
+        shared.usb.lock(|usb| {
             let on = *local.on;
-            let note_num = *local.note_num;
-            let chan = MidiChannel::Channel1;
-            let note = Note::try_from_primitive(note_num).unwrap();
-            let vel = U7::from_clamped(127);
-            let packet = UsbMidiEventPacket::from_midi(
-                usbd_midi::data::usb_midi::cable_number::CableNumber::Cable0,
-                if on {
-                    Message::NoteOn(chan, note, vel)
-                } else {
-                    Message::NoteOff(chan, note, vel)
-                },
-            );
-            if midi.send_message(packet).is_ok() {
+            let note = *local.note_num;
+            let event = if on {
+                MidiEvent::NoteOn {
+                    channel: 0,
+                    note,
+                    velocity: 127,
+                }
+            } else {
+                MidiEvent::NoteOff {
+                    channel: 0,
+                    note,
+                    velocity: 127,
+                }
+            };
+            if usb.send(event).is_ok() {
                 *local.on = !on;
                 if !on {
-                    *local.note_num = (note_num + 1) % 127;
+                    *local.note_num = (note + 1) % 127;
                 }
             }
         });
@@ -196,28 +159,19 @@ mod app {
     #[task(binds = OTG_FS, shared = [usb], local = [seed_led])]
     fn usb_event(mut ctx: usb_event::Context) {
         let (local, shared) = (&mut ctx.local, &mut ctx.shared);
-        shared.usb.lock(|(usb_dev, midi)| {
+        shared.usb.lock(|usb| {
             let led = &mut local.seed_led;
 
-            if !usb_dev.poll(&mut [midi]) {
-                return;
-            }
-
-            let mut buffer = [0; 64];
-            if let Ok(size) = midi.read(&mut buffer) {
-                let buffer_reader = MidiPacketBufferReader::new(&buffer, size);
-                for packet in buffer_reader.into_iter() {
-                    if let Ok(packet) = packet {
-                        match packet.message {
-                            Message::NoteOn(_, _, U7::MIN) | Message::NoteOff(..) => {
-                                led.set_low().unwrap();
-                            }
-                            Message::NoteOn(..) => {
-                                led.set_high().unwrap();
-                            }
-                            _ => {}
-                        }
+            usb.poll();
+            while let Some(event) = usb.read() {
+                match event {
+                    MidiEvent::NoteOff { .. } => {
+                        led.set_low().unwrap();
+                    }
+                    MidiEvent::NoteOn { .. } => {
+                        led.set_high().unwrap();
                     }
+                    _ => {}
                 }
             }
         });
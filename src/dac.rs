@@ -0,0 +1,310 @@
+//! Internal 12-bit DAC on Daisy pins 23 (PA4, channel 1) and 22 (PA5, channel 2).
+//! Supports one-shot writes and DMA driven waveforms paced by a timer trigger.
+//!
+//! # Example
+//!
+//! ```rust
+//! // One-shot
+//! let (mut cv1, mut cv2) = dac::init(device.DAC, ccdr.peripheral.DAC12, daisy23, daisy22, &mut delay);
+//! cv1.set_volts(1.5);
+//!
+//! // Waveform, one value per TIM6 update
+//! let _timer6 = device.TIM6.timer(1.khz(), ccdr.peripheral.TIM6, &ccdr.clocks);
+//! dac::enable_trigger_output(dac::DacTrigger::Tim6);
+//! let streams = dma::dma::StreamsTuple::new(device.DMA2, ccdr.peripheral.DMA2);
+//! let config = dma::dma::DmaConfig::default()
+//!     .memory_increment(true)
+//!     .circular_buffer(true);
+//! let mut transfer: dma::Transfer<_, _, dma::MemoryToPeripheral, _, _> =
+//!     dma::Transfer::init(streams.0, cv2, waveform, None, config);
+//! transfer.start(|cv2| cv2.enable_dma(dac::DacTrigger::Tim6));
+//! ```
+use stm32h7xx_hal::{
+    dac::{self, Enabled},
+    dma,
+    gpio::{gpioa, Analog},
+    hal::blocking::delay::DelayUs,
+    prelude::*,
+    rcc, stm32,
+};
+
+/// Largest DAC code.
+pub const DAC_MAX: u16 = 4095;
+
+/// Nominal full scale output voltage.
+pub const DAC_VREF: f32 = 3.3;
+
+// DMAMUX1 request lines
+const DAC_CH1_DMA: u8 = 67;
+const DAC_CH2_DMA: u8 = 68;
+
+// Control register bits for channel 1, channel 2 is offset by 16
+const CR_EN: u32 = 1 << 0;
+const CR_TEN: u32 = 1 << 1;
+const CR_TSEL_SHIFT: u32 = 2;
+const CR_TSEL_MASK: u32 = 0b1111 << CR_TSEL_SHIFT;
+const CR_DMAEN: u32 = 1 << 12;
+const CR_CHANNEL2_SHIFT: u32 = 16;
+
+/// Timer trigger output that paces DMA transfers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DacTrigger {
+    Tim1,
+    Tim2,
+    Tim4,
+    Tim5,
+    Tim6,
+    Tim7,
+    Tim8,
+    Tim15,
+}
+
+impl DacTrigger {
+    fn tsel(self) -> u32 {
+        match self {
+            DacTrigger::Tim1 => 1,
+            DacTrigger::Tim2 => 2,
+            DacTrigger::Tim4 => 3,
+            DacTrigger::Tim5 => 4,
+            DacTrigger::Tim6 => 5,
+            DacTrigger::Tim7 => 6,
+            DacTrigger::Tim8 => 7,
+            DacTrigger::Tim15 => 8,
+        }
+    }
+}
+
+/// Configure the timer's trigger output (TRGO) to fire on every update.
+pub fn enable_trigger_output(trigger: DacTrigger) {
+    const MMS_UPDATE: u8 = 0b010;
+    macro_rules! update_trgo {
+        ($tim:ident) => {
+            (*stm32::$tim::ptr())
+                .cr2
+                .modify(|_, w| w.mms().bits(MMS_UPDATE))
+        };
+    }
+    unsafe {
+        match trigger {
+            DacTrigger::Tim1 => update_trgo!(TIM1),
+            DacTrigger::Tim2 => update_trgo!(TIM2),
+            DacTrigger::Tim4 => update_trgo!(TIM4),
+            DacTrigger::Tim5 => update_trgo!(TIM5),
+            DacTrigger::Tim6 => update_trgo!(TIM6),
+            DacTrigger::Tim7 => update_trgo!(TIM7),
+            DacTrigger::Tim8 => update_trgo!(TIM8),
+            DacTrigger::Tim15 => update_trgo!(TIM15),
+        }
+    }
+}
+
+/// Calibration from volts to DAC codes, `code = volts * gain + offset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DacCalibration {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Default for DacCalibration {
+    /// Nominal 0V to [DAC_VREF].
+    fn default() -> Self {
+        Self::full_scale(DAC_VREF)
+    }
+}
+
+impl DacCalibration {
+    /// Nominal calibration for an output from 0V to `volts` at full scale.
+    pub fn full_scale(volts: f32) -> Self {
+        Self {
+            gain: DAC_MAX as f32 / volts,
+            offset: 0.0,
+        }
+    }
+
+    /// Compute a calibration from two codes and the voltages measured at the output.
+    /// Returns `None` if the voltages are the same.
+    pub fn from_references(code_a: u16, volts_a: f32, code_b: u16, volts_b: f32) -> Option<Self> {
+        let delta = volts_b - volts_a;
+        if delta == 0.0 {
+            return None;
+        }
+        let gain = (code_b as f32 - code_a as f32) / delta;
+        Some(Self {
+            gain,
+            offset: code_a as f32 - volts_a * gain,
+        })
+    }
+
+    /// Convert volts to a DAC code, clamped to the DAC range.
+    pub fn code(&self, volts: f32) -> u16 {
+        (volts * self.gain + self.offset + 0.5).clamp(0.0, DAC_MAX as f32) as u16
+    }
+}
+
+/// A DAC channel from the HAL.
+pub trait DacChannel {
+    /// Channel index, 0 or 1.
+    const INDEX: usize;
+    /// DMAMUX request line.
+    const REQUEST_LINE: u8;
+}
+
+impl DacChannel for dac::C1<stm32::DAC, Enabled> {
+    const INDEX: usize = 0;
+    const REQUEST_LINE: u8 = DAC_CH1_DMA;
+}
+
+impl DacChannel for dac::C2<stm32::DAC, Enabled> {
+    const INDEX: usize = 1;
+    const REQUEST_LINE: u8 = DAC_CH2_DMA;
+}
+
+/// DAC channel 1 on Daisy pin 23.
+pub type DacOutput1 = DacOutput<dac::C1<stm32::DAC, Enabled>>;
+/// DAC channel 2 on Daisy pin 22.
+pub type DacOutput2 = DacOutput<dac::C2<stm32::DAC, Enabled>>;
+
+/// A calibrated DAC output.
+pub struct DacOutput<C> {
+    channel: C,
+    calibration: DacCalibration,
+}
+
+fn regs() -> &'static stm32::dac::RegisterBlock {
+    unsafe { &*stm32::DAC::ptr() }
+}
+
+impl<C> DacOutput<C>
+where
+    C: DacChannel,
+{
+    fn new(channel: C) -> Self {
+        Self {
+            channel,
+            calibration: DacCalibration::default(),
+        }
+    }
+
+    fn cr_bits(bits: u32) -> u32 {
+        bits << (C::INDEX as u32 * CR_CHANNEL2_SHIFT)
+    }
+
+    fn data_address() -> usize {
+        let dac = regs();
+        match C::INDEX {
+            0 => &dac.dhr12r1 as *const _ as usize,
+            _ => &dac.dhr12r2 as *const _ as usize,
+        }
+    }
+
+    /// Write a raw 12 bit code.
+    pub fn set_value(&mut self, code: u16) {
+        let code = code.min(DAC_MAX) as u32;
+        let dac = regs();
+        match C::INDEX {
+            0 => dac.dhr12r1.write(|w| unsafe { w.bits(code) }),
+            _ => dac.dhr12r2.write(|w| unsafe { w.bits(code) }),
+        }
+    }
+
+    /// Write a value from 0.0 to 1.0 of the full scale.
+    pub fn set_normalized(&mut self, value: f32) {
+        self.set_value((value.clamp(0.0, 1.0) * DAC_MAX as f32 + 0.5) as u16);
+    }
+
+    /// Write a voltage using the calibration.
+    pub fn set_volts(&mut self, volts: f32) {
+        self.set_value(self.calibration.code(volts));
+    }
+
+    /// Set the calibration.
+    pub fn set_calibration(&mut self, calibration: DacCalibration) {
+        self.calibration = calibration;
+    }
+
+    /// Get the calibration.
+    pub fn calibration(&self) -> DacCalibration {
+        self.calibration
+    }
+
+    /// Convert voltages to codes for a DMA waveform buffer using the calibration.
+    pub fn volts_to_codes(&self, volts: &[f32], codes: &mut [u32]) {
+        for (code, volts) in codes.iter_mut().zip(volts.iter()) {
+            *code = self.calibration.code(*volts) as u32;
+        }
+    }
+
+    /// Enable DMA requests paced by `trigger`, call this when starting the DMA transfer.
+    pub fn enable_dma(&mut self, trigger: DacTrigger) {
+        let set = Self::cr_bits(CR_TEN | CR_DMAEN | (trigger.tsel() << CR_TSEL_SHIFT));
+        let clear = Self::cr_bits(CR_TSEL_MASK);
+        let en = Self::cr_bits(CR_EN);
+        // The trigger can only be changed while the channel is disabled
+        regs()
+            .cr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(clear | en)) | set) });
+        regs().cr.modify(|r, w| unsafe { w.bits(r.bits() | en) });
+    }
+
+    /// Disable DMA requests, the output holds its last value.
+    pub fn disable_dma(&mut self) {
+        let clear = Self::cr_bits(CR_TEN | CR_DMAEN | CR_TSEL_MASK);
+        regs()
+            .cr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !clear) });
+    }
+
+    /// Release the HAL channel.
+    pub fn free(self) -> C {
+        self.channel
+    }
+}
+
+unsafe impl<C> dma::traits::TargetAddress<dma::MemoryToPeripheral> for DacOutput<C>
+where
+    C: DacChannel,
+{
+    type MemSize = u32;
+
+    const REQUEST_LINE: Option<u8> = Some(C::REQUEST_LINE);
+
+    fn address(&self) -> usize {
+        Self::data_address()
+    }
+}
+
+/// Setup both DAC channels, buffered and calibrated.
+pub fn init<D: DelayUs<u32>>(
+    dac: stm32::DAC,
+    prec: rcc::rec::Dac12,
+    daisy23: gpioa::PA4<Analog>,
+    daisy22: gpioa::PA5<Analog>,
+    delay: &mut D,
+) -> (DacOutput1, DacOutput2) {
+    let (ch1, ch2) = dac.dac((daisy23, daisy22), prec);
+    let ch1 = ch1.calibrate_buffer(delay).enable();
+    let ch2 = ch2.calibrate_buffer(delay).enable();
+    (DacOutput::new(ch1), DacOutput::new(ch2))
+}
+
+/// Setup DAC channel 1 on Daisy pin 23, buffered and calibrated.
+pub fn init_daisy23<D: DelayUs<u32>>(
+    dac: stm32::DAC,
+    prec: rcc::rec::Dac12,
+    daisy23: gpioa::PA4<Analog>,
+    delay: &mut D,
+) -> DacOutput1 {
+    let ch1 = dac.dac(daisy23, prec);
+    DacOutput::new(ch1.calibrate_buffer(delay).enable())
+}
+
+/// Setup DAC channel 2 on Daisy pin 22, buffered and calibrated.
+pub fn init_daisy22<D: DelayUs<u32>>(
+    dac: stm32::DAC,
+    prec: rcc::rec::Dac12,
+    daisy22: gpioa::PA5<Analog>,
+    delay: &mut D,
+) -> DacOutput2 {
+    let ch2 = dac.dac(daisy22, prec);
+    DacOutput::new(ch2.calibrate_buffer(delay).enable())
+}
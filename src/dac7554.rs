@@ -0,0 +1,111 @@
+//! Driver for the DAC7554 quad 12-bit SPI DAC, used for CV outputs on Daisy Patch style
+//! hardware. Generic over the SPI bus and SYNC pin so it can be used with any board.
+//!
+//! The DAC7554 latches data on the falling clock edge, use [MODE_1](stm32h7xx_hal::spi::MODE_1)
+//! or [MODE_2](stm32h7xx_hal::spi::MODE_2) with up to 50MHz clock.
+use stm32h7xx_hal::hal::{blocking::spi::Write, digital::v2::OutputPin};
+
+use crate::dac::{DacCalibration, DAC_MAX};
+
+/// Number of DAC channels.
+pub const DAC7554_CHANNELS: usize = 4;
+
+/// Nominal full scale output voltage, with a 5V reference and no output stage.
+pub const DAC7554_FULL_SCALE: f32 = 5.0;
+
+// Control bits
+const LOAD_INPUT: u16 = 0b00 << 14;
+const LOAD_UPDATE: u16 = 0b01 << 14;
+const LOAD_UPDATE_ALL: u16 = 0b10 << 14;
+const ADDRESS_SHIFT: u16 = 12;
+
+/// A DAC7554 with per channel calibration.
+pub struct Dac7554<SPI, SYNC> {
+    spi: SPI,
+    sync: SYNC,
+    calibration: [DacCalibration; DAC7554_CHANNELS],
+    values: [u16; DAC7554_CHANNELS],
+}
+
+impl<SPI, SYNC> Dac7554<SPI, SYNC>
+where
+    SPI: Write<u8>,
+    SYNC: OutputPin,
+{
+    /// Create a new DAC, all channels are set to 0.
+    pub fn new(spi: SPI, mut sync: SYNC) -> Self {
+        sync.set_high().ok().unwrap();
+        let mut dac = Self {
+            spi,
+            sync,
+            calibration: [DacCalibration::full_scale(DAC7554_FULL_SCALE); DAC7554_CHANNELS],
+            values: [0; DAC7554_CHANNELS],
+        };
+        dac.set_values([0; DAC7554_CHANNELS]);
+        dac
+    }
+
+    fn write(&mut self, control: u16, channel: usize, code: u16) {
+        let word = control | ((channel as u16) << ADDRESS_SHIFT) | code.min(DAC_MAX);
+        self.sync.set_low().ok().unwrap();
+        self.spi.write(&word.to_be_bytes()).ok().unwrap();
+        self.sync.set_high().ok().unwrap();
+        self.values[channel] = code.min(DAC_MAX);
+    }
+
+    /// Write a raw 12 bit code to `channel` (0..4) and update its output.
+    pub fn set_value(&mut self, channel: usize, code: u16) {
+        assert!(channel < DAC7554_CHANNELS);
+        self.write(LOAD_UPDATE, channel, code);
+    }
+
+    /// Write raw 12 bit codes to all channels and update the outputs simultaneously.
+    pub fn set_values(&mut self, codes: [u16; DAC7554_CHANNELS]) {
+        for (channel, code) in codes.iter().enumerate() {
+            let control = if channel == DAC7554_CHANNELS - 1 {
+                LOAD_UPDATE_ALL
+            } else {
+                LOAD_INPUT
+            };
+            self.write(control, channel, *code);
+        }
+    }
+
+    /// Write a voltage to `channel` using its calibration.
+    pub fn set_volts(&mut self, channel: usize, volts: f32) {
+        assert!(channel < DAC7554_CHANNELS);
+        self.set_value(channel, self.calibration[channel].code(volts));
+    }
+
+    /// Write voltages to all channels using their calibrations, updated simultaneously.
+    pub fn set_all_volts(&mut self, volts: [f32; DAC7554_CHANNELS]) {
+        let mut codes = [0; DAC7554_CHANNELS];
+        for (code, (volts, calibration)) in codes
+            .iter_mut()
+            .zip(volts.iter().zip(self.calibration.iter()))
+        {
+            *code = calibration.code(*volts);
+        }
+        self.set_values(codes);
+    }
+
+    /// The last code written to `channel`.
+    pub fn value(&self, channel: usize) -> u16 {
+        self.values[channel]
+    }
+
+    /// Set the calibration for `channel`, e.g. to account for the board's output stage.
+    pub fn set_calibration(&mut self, channel: usize, calibration: DacCalibration) {
+        self.calibration[channel] = calibration;
+    }
+
+    /// Get the calibration for `channel`.
+    pub fn calibration(&self, channel: usize) -> DacCalibration {
+        self.calibration[channel]
+    }
+
+    /// Release the SPI bus and SYNC pin.
+    pub fn free(self) -> (SPI, SYNC) {
+        (self.spi, self.sync)
+    }
+}
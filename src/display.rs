@@ -0,0 +1,114 @@
+//! SSD1306 and SSD1309 OLED displays over SPI or I2C, e.g. the Field's display, a Patch or a
+//! custom panel.
+//!
+//! The displays are the [ssd1306] and [ssd1309] drivers in graphics mode, draw on them with
+//! embedded-graphics and call `flush` to send the framebuffer.
+//!
+//! # Example
+//!
+//! ```rust
+//! // SPI1 on Daisy pins 8 (SCK) and 10 (MOSI), DC on pin 9 and CS on pin 7
+//! let spi: hal::spi::Spi<_, _, u8> = device.SPI1.spi(
+//!     (daisy8.into_alternate_af5(), hal::spi::NoMiso, daisy10.into_alternate_af5()),
+//!     hal::spi::MODE_0,
+//!     3.mhz(),
+//!     ccdr.peripheral.SPI1,
+//!     &ccdr.clocks,
+//! );
+//! let mut oled = display::ssd1309_spi(
+//!     spi,
+//!     daisy9.into_push_pull_output(),
+//!     daisy7.into_push_pull_output(),
+//! );
+//!
+//! // Or I2C1 on Daisy pins 11 (SCL) and 12 (SDA)
+//! let i2c = device.I2C1.i2c(
+//!     (daisy11.into_alternate_af4().set_open_drain(), daisy12.into_alternate_af4().set_open_drain()),
+//!     400.khz(),
+//!     ccdr.peripheral.I2C1,
+//!     &ccdr.clocks,
+//! );
+//! let mut oled = display::ssd1306_i2c(i2c, I2C_ADDR, DisplaySize128x32);
+//!
+//! oled.init().unwrap();
+//! Circle::new(Point::new(10, 10), 8)
+//!     .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+//!     .draw(&mut oled)
+//!     .unwrap();
+//! oled.flush().unwrap();
+//! ```
+use display_interface_i2c::I2CInterface;
+use display_interface_spi::SPIInterface;
+use ssd1306::{mode::BufferedGraphicsMode, rotation::DisplayRotation, size::DisplaySize};
+use stm32h7xx_hal::hal::{
+    blocking::{i2c, spi},
+    digital::v2::OutputPin,
+};
+
+pub use ssd1306::size::{DisplaySize128x32, DisplaySize128x64, DisplaySize64x48, DisplaySize96x16};
+
+/// I2C address with the address pin (SA0) low, it's 0x3D when high.
+pub const I2C_ADDR: u8 = 0x3C;
+
+//control byte that precedes display data on I2C
+const I2C_DATA: u8 = 0x40;
+
+/// A 128x64 SSD1309 in graphics mode.
+pub type Ssd1309<DI> = ssd1309::prelude::GraphicsMode<DI>;
+
+/// An SSD1306 of size `SIZE` in graphics mode.
+pub type Ssd1306<DI, SIZE> = ssd1306::Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>;
+
+/// Create an SSD1309 on SPI, it still needs to be initialized.
+pub fn ssd1309_spi<SPI, DC, CS>(spi: SPI, dc: DC, cs: CS) -> Ssd1309<SPIInterface<SPI, DC, CS>>
+where
+    SPI: spi::Write<u8>,
+    DC: OutputPin,
+    CS: OutputPin,
+{
+    ssd1309::Builder::new()
+        .connect(SPIInterface::new(spi, dc, cs))
+        .into()
+}
+
+/// Create an SSD1309 on I2C at `addr`, it still needs to be initialized.
+pub fn ssd1309_i2c<I2C: i2c::Write>(i2c: I2C, addr: u8) -> Ssd1309<I2CInterface<I2C>> {
+    ssd1309::Builder::new()
+        .connect(I2CInterface::new(i2c, addr, I2C_DATA))
+        .into()
+}
+
+/// Create an SSD1306 on SPI, it still needs to be initialized.
+pub fn ssd1306_spi<SPI, DC, CS, SIZE>(
+    spi: SPI,
+    dc: DC,
+    cs: CS,
+    size: SIZE,
+) -> Ssd1306<SPIInterface<SPI, DC, CS>, SIZE>
+where
+    SPI: spi::Write<u8>,
+    DC: OutputPin,
+    CS: OutputPin,
+    SIZE: DisplaySize,
+{
+    ssd1306::Ssd1306::new(
+        SPIInterface::new(spi, dc, cs),
+        size,
+        DisplayRotation::Rotate0,
+    )
+    .into_buffered_graphics_mode()
+}
+
+/// Create an SSD1306 on I2C at `addr`, it still needs to be initialized.
+pub fn ssd1306_i2c<I2C, SIZE>(i2c: I2C, addr: u8, size: SIZE) -> Ssd1306<I2CInterface<I2C>, SIZE>
+where
+    I2C: i2c::Write,
+    SIZE: DisplaySize,
+{
+    ssd1306::Ssd1306::new(
+        I2CInterface::new(i2c, addr, I2C_DATA),
+        size,
+        DisplayRotation::Rotate0,
+    )
+    .into_buffered_graphics_mode()
+}
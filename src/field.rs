@@ -1,60 +1,99 @@
 //! Setup for the Daisy Field.
+use crate::dac::DacCalibration;
+use crate::gpio::{Daisy16, Daisy17, Daisy18, Daisy19, Daisy20, Daisy21, Daisy24, Daisy25};
+use crate::hid::{
+    push_event, AnalogControl, CvCalibration, EventProducer, EventSource, HidEvent, GAMMA,
+};
+use crate::notes::{KeyNotes, NoteEvent};
+use crate::pca9685::{Config, OutputDisabled, Pca9685, PCA9685_BASE_ADDR};
+use debouncr::{debounce_4, Debouncer, Edge, Repeat4};
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use heapless::Vec;
 use shift::{Delay as ShiftDelay, ShiftClockDelay, ShiftIn};
-use stm32h7xx_hal::{self as hal, gpio::Analog, prelude::*};
+use stm32h7xx_hal::{
+    self as hal,
+    gpio::{Analog, Output, PushPull},
+    hal::digital::v2::OutputPin,
+    prelude::*,
+};
 
 type I2CWrite = dyn hal::hal::blocking::i2c::Write<Error = hal::i2c::Error>;
 
-const BASE_ADDR: u8 = 0b01000000;
-const LED_ADDR0: u8 = BASE_ADDR | 0x00;
-const LED_ADDR1: u8 = BASE_ADDR | 0x02;
+const LED_ADDR0: u8 = PCA9685_BASE_ADDR | 0x00;
+const LED_ADDR1: u8 = PCA9685_BASE_ADDR | 0x02;
 
-const PCA9685_MODE1: u8 = 0x00; // location for Mode1 register address
-const PCA9685_LED0: u8 = 0x06; // location for start of LED0 registers
-const PCA9685_AUTO_INC: u8 = 0b0010_0000;
+/// How many times [FieldLeds::draw] tries to send a driver before giving up.
+pub const LED_DRAW_ATTEMPTS: usize = 3;
+
+/// Number of knobs.
+pub const FIELD_KNOB_COUNT: usize = 8;
+
+/// Number of CV inputs.
+pub const FIELD_CV_IN_COUNT: usize = 4;
 
 /// Display Size width, height
 pub const FIELD_DISPLAY_SIZE: (usize, usize) = (128, 64);
 
-const GAMMA: [u16; 256] = [
-    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 8, 9, 10,
-    11, 12, 13, 15, 16, 17, 18, 20, 21, 23, 25, 26, 28, 30, 32, 34, 36, 38, 40, 43, 45, 48, 50, 53,
-    56, 59, 62, 65, 68, 71, 75, 78, 82, 85, 89, 93, 97, 101, 105, 110, 114, 119, 123, 128, 133,
-    138, 143, 149, 154, 159, 165, 171, 177, 183, 189, 195, 202, 208, 215, 222, 229, 236, 243, 250,
-    258, 266, 273, 281, 290, 298, 306, 315, 324, 332, 341, 351, 360, 369, 379, 389, 399, 409, 419,
-    430, 440, 451, 462, 473, 485, 496, 508, 520, 532, 544, 556, 569, 582, 594, 608, 621, 634, 648,
-    662, 676, 690, 704, 719, 734, 749, 764, 779, 795, 811, 827, 843, 859, 876, 893, 910, 927, 944,
-    962, 980, 998, 1016, 1034, 1053, 1072, 1091, 1110, 1130, 1150, 1170, 1190, 1210, 1231, 1252,
-    1273, 1294, 1316, 1338, 1360, 1382, 1404, 1427, 1450, 1473, 1497, 1520, 1544, 1568, 1593, 1617,
-    1642, 1667, 1693, 1718, 1744, 1770, 1797, 1823, 1850, 1877, 1905, 1932, 1960, 1988, 2017, 2045,
-    2074, 2103, 2133, 2162, 2192, 2223, 2253, 2284, 2315, 2346, 2378, 2410, 2442, 2474, 2507, 2540,
-    2573, 2606, 2640, 2674, 2708, 2743, 2778, 2813, 2849, 2884, 2920, 2957, 2993, 3030, 3067, 3105,
-    3143, 3181, 3219, 3258, 3297, 3336, 3376, 3416, 3456, 3496, 3537, 3578, 3619, 3661, 3703, 3745,
-    3788, 3831, 3874, 3918, 3962, 4006, 4050, 4095,
-];
-
 pub type FieldSwitches = (
     hal::gpio::gpiob::PB15<hal::gpio::Input<hal::gpio::PullUp>>,
     hal::gpio::gpiob::PB14<hal::gpio::Input<hal::gpio::PullUp>>,
 );
 
+/// Gate input and output, the input can be wrapped in a [GateIn](crate::gate::GateIn)
+/// (inverted).
 pub type FieldGates = (
     hal::gpio::gpiob::PB12<hal::gpio::Input<hal::gpio::Floating>>,
     hal::gpio::gpioc::PC0<hal::gpio::Output<hal::gpio::PushPull>>,
 );
 
+/// CV outputs 1 and 2, driven by the internal DAC on Daisy pins 23 and 22.
+pub type FieldCvOut = (crate::dac::DacOutput1, crate::dac::DacOutput2);
+
+/// Nominal full scale voltage of the CV outputs.
+pub const FIELD_CV_OUT_VOLTS: f32 = 5.0;
+
+/// The DAC and pins for the CV outputs, passed to [Field::new].
+pub struct FieldCvOutPins {
+    pub dac: hal::stm32::DAC,
+    pub dac_rec: hal::rcc::rec::Dac12,
+    pub out1: crate::gpio::Daisy23<Analog>,
+    pub out2: crate::gpio::Daisy22<Analog>,
+}
+
+/// The ADC and pins for the knobs and CV inputs, passed to [Field::new].
+pub struct FieldControlPins {
+    pub adc: hal::adc::Adc<hal::stm32::ADC1, hal::adc::Disabled>,
+    /// Output of the knob multiplexer.
+    pub knob_mux: Daisy16<Analog>,
+    /// Multiplexer select lines, least significant first.
+    pub mux_select: (Daisy21<Analog>, Daisy20<Analog>, Daisy19<Analog>),
+    /// CV inputs 1 to 4.
+    pub cv_in: (
+        Daisy17<Analog>,
+        Daisy18<Analog>,
+        Daisy25<Analog>,
+        Daisy24<Analog>,
+    ),
+}
+
+type FieldDisplaySpi = hal::spi::Spi<hal::device::SPI1, hal::spi::Enabled>;
+type FieldDisplayDc = hal::gpio::gpiob::PB4<hal::gpio::Output<hal::gpio::PushPull>>;
+type FieldDisplayCs = hal::gpio::gpiog::PG10<hal::gpio::Output<hal::gpio::PushPull>>;
+
+/// Blocking display, see [FieldDisplayDma] for one that flushes without blocking.
 pub type FieldDisplay = ssd1309::prelude::GraphicsMode<
-    display_interface_spi::SPIInterface<
-        hal::spi::Spi<hal::device::SPI1, hal::spi::Enabled>,
-        hal::gpio::gpiob::PB4<hal::gpio::Output<hal::gpio::PushPull>>,
-        hal::gpio::gpiog::PG10<hal::gpio::Output<hal::gpio::PushPull>>,
-    >,
+    display_interface_spi::SPIInterface<FieldDisplaySpi, FieldDisplayDc, FieldDisplayCs>,
 >;
 
+/// Serial MIDI on USART1, parse it with a [MidiParser](crate::midi::MidiParser) or use it
+/// with interrupts through a [MidiUart](crate::midi::MidiUart).
 pub type FieldSerialMIDI = hal::serial::Serial<hal::stm32::USART1>;
 
 pub struct FieldLeds {
     i2c: hal::i2c::I2c<hal::stm32::I2C1>,
     drivers: [LedDriver; 2],
+    transfer: Option<LedTransfer>,
+    error: Option<LedError>,
 }
 
 pub struct Field {
@@ -62,119 +101,132 @@ pub struct Field {
     keyboard: Option<FieldKeyboard>,
     switches: Option<FieldSwitches>,
     gates: Option<FieldGates>,
-    display: Option<FieldDisplay>,
+    cv_out: Option<FieldCvOut>,
+    controls: Option<FieldControls>,
+    display: Option<(FieldDisplaySpi, FieldDisplayDc, FieldDisplayCs)>,
     serial_midi: Option<FieldSerialMIDI>,
 }
 
-#[derive(Clone, Copy)]
-#[repr(C, packed)]
-struct Led {
-    on: u16,
-    off: u16,
-}
-
-//struct representing an entire, auto incremented, update
-#[repr(C, packed)]
-struct LedTxBuffer {
-    reg: u8,
-    leds: [Led; 16],
-}
-
-impl Default for Led {
-    fn default() -> Self {
-        //full off
-        Self { on: 0, off: 0x1001 }
-    }
+/// Errors from the LED drivers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedError {
+    /// The driver at the address did not acknowledge, e.g. the cable is loose.
+    Nack(u8),
+    /// A bus error or lost arbitration, e.g. from a glitch on the cable.
+    Bus,
+    /// SDA is held low and bus recovery could not release it.
+    Stuck,
 }
 
-impl Default for LedTxBuffer {
-    fn default() -> Self {
-        let leds: [Led; 16] = Default::default();
-        Self {
-            reg: PCA9685_LED0,
-            leds,
+impl LedError {
+    fn from_i2c(error: hal::i2c::Error, addr: u8) -> Self {
+        match error {
+            hal::i2c::Error::NotAcknowledge => LedError::Nack(addr),
+            _ => LedError::Bus,
         }
     }
 }
 
-///PCA9685 Led Driver
+pub type LedResult<T> = Result<T, LedError>;
+
+///PCA9685 Led Driver, configured for the Field's LEDs.
 pub struct LedDriver {
-    addr: u8,
-    buffer: LedTxBuffer,
+    pca: Pca9685,
+    dirty: bool,
+    configured: bool,
 }
 
-//TODO DMA
 impl LedDriver {
     ///Initialize the Led driver with the given address.
     pub fn new(i2c: &mut I2CWrite, addr: u8) -> Self {
-        //configure, copied from libDaisy
-        //mode 1:
-        //  auto increment
-        //mode 2:
-        //  OE-high = high Impedance
-        //  Push-Pull outputs
-        //  outputs change on STOP
-        //  outputs inverted
-        i2c.write(addr, &[PCA9685_MODE1, PCA9685_AUTO_INC, 0b0011_0110])
-            .unwrap();
-        //turn all, full off
-        i2c.write(addr, &[0xFA, 0, 0, 0, 0x10]).unwrap();
+        let mut driver = Self::unconfigured(addr);
+        driver.configure(i2c).unwrap();
+        driver
+    }
 
+    ///Create the Led driver with the given address, it is configured by the first draw.
+    pub fn unconfigured(addr: u8) -> Self {
+        //configuration copied from libDaisy
+        let config = Config {
+            invert: true,
+            totem_pole: true,
+            change_on_ack: false,
+            output_disabled: OutputDisabled::HighImpedance,
+            frequency: None,
+            external_clock: None,
+        };
+        let mut pca = Pca9685::new(addr, config);
+        pca.set_gamma(Some(&GAMMA));
+        pca.set_stagger(true);
         Self {
-            addr,
-            buffer: Default::default(),
+            pca,
+            dirty: true,
+            configured: false,
         }
     }
 
+    ///Configure the driver, this is done automatically by [LedDriver::draw].
+    pub fn configure(&mut self, i2c: &mut I2CWrite) -> LedResult<()> {
+        self.configured = false;
+        let addr = self.pca.addr();
+        self.pca
+            .configure(i2c)
+            .map_err(|e| LedError::from_i2c(e, addr))?;
+        //the buffer was reset to off, the LEDs need to be sent again
+        self.dirty = true;
+        self.configured = true;
+        Ok(())
+    }
+
     /// Set all the buffered values for all LEDs to the given brightness.
     pub fn set_all(&mut self, brightness: u8) {
-        let cycles = GAMMA[brightness as usize];
-
-        //full off
-        if cycles == 0 {
-            for (index, mut led) in self.buffer.leds.iter_mut().enumerate() {
-                let on = (index << 2) as u16; //offset on times
-                led.on = on;
-                led.off = 0x1001 + on;
-            }
-        } else {
-            for (index, mut led) in self.buffer.leds.iter_mut().enumerate() {
-                let on = (index << 2) as u16; //offset on times
-                led.on = if cycles >= 0x0FFF { 0x1000 | on } else { on };
-                led.off = on.saturating_add(cycles) & 0x0FFF;
-            }
-        }
+        self.dirty |= self.pca.set_brightness_all(brightness);
     }
 
     /// Set the buffered value for the given LED to the given brightness.
     pub fn set(&mut self, index: usize, brightness: u8) {
         assert!(index < 16);
-        let cycles = GAMMA[brightness as usize];
+        self.dirty |= self.pca.set_brightness(index, brightness);
+    }
 
-        let led: &mut Led = &mut self.buffer.leds[index];
+    /// If the buffer has changed since it was last drawn by [FieldLeds].
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
 
-        let on = (index << 2) as u16; //offset on times
-                                      //full off
-        if cycles == 0 {
-            led.on = on;
-            led.off = 0x1001 + on;
-        } else {
-            led.on = if cycles >= 0x0FFF { 0x1000 | on } else { on };
-            led.off = on.saturating_add(cycles) & 0x0FFF;
-        }
+    fn bytes(&self) -> &[u8] {
+        self.pca.bytes()
     }
 
-    /// Update all the leds.
-    pub fn draw(&self, i2c: &mut I2CWrite) {
-        i2c.write(self.addr, unsafe {
-            core::slice::from_raw_parts(
-                core::mem::transmute::<_, *const u8>(&self.buffer),
-                core::mem::size_of::<LedTxBuffer>(),
-            )
+    /// Update all the leds, configuring the driver first if needed.
+    pub fn draw(&mut self, i2c: &mut I2CWrite) -> LedResult<()> {
+        if !self.configured {
+            self.configure(i2c)?;
+        }
+        let addr = self.pca.addr();
+        self.pca.write(i2c).map_err(|e| {
+            //it may have been reset, e.g. by a reconnected cable
+            self.configured = false;
+            LedError::from_i2c(e, addr)
         })
-        .unwrap();
     }
+
+    /// Get the underlying PCA9685 driver.
+    pub fn pca9685(&mut self) -> &mut Pca9685 {
+        &mut self.pca
+    }
+}
+
+//progress of an interrupt driven draw
+struct LedTransfer {
+    driver: usize,
+    sent: usize,
+}
+
+fn i2c_regs() -> &'static hal::stm32::i2c1::RegisterBlock {
+    unsafe { &*hal::stm32::I2C1::ptr() }
 }
+
 impl FieldLeds {
     pub fn new(
         i2cd: hal::stm32::I2C1,
@@ -193,11 +245,20 @@ impl FieldLeds {
             clocks,
         );
 
-        let drivers = [
-            LedDriver::new(&mut i2c, LED_ADDR0),
-            LedDriver::new(&mut i2c, LED_ADDR1),
+        let mut drivers = [
+            LedDriver::unconfigured(LED_ADDR0),
+            LedDriver::unconfigured(LED_ADDR1),
         ];
-        Self { i2c, drivers }
+        //drivers that fail are configured again on the next draw
+        for driver in drivers.iter_mut() {
+            let _ = driver.configure(&mut i2c);
+        }
+        Self {
+            i2c,
+            drivers,
+            transfer: None,
+            error: None,
+        }
     }
 
     pub fn button_set(&mut self, index: usize, brightness: u8) {
@@ -224,9 +285,238 @@ impl FieldLeds {
         self.drivers[1].set_all(brightness);
     }
 
-    pub fn draw(&mut self) {
-        for driver in self.drivers.iter() {
-            driver.draw(&mut self.i2c)
+    /// Send the drivers that changed since they were last drawn, blocking.
+    /// Each driver is tried up to [LED_DRAW_ATTEMPTS] times, recovering the bus in between.
+    /// Drivers that fail stay changed so they are sent again on the next draw, and the first
+    /// error is returned. Does nothing while an interrupt driven draw is in progress.
+    pub fn draw(&mut self) -> LedResult<()> {
+        if self.transfer.is_some() {
+            return Ok(());
+        }
+        if self.error.take().is_some() || i2c_regs().isr.read().busy().bit_is_set() {
+            self.recover_bus()?;
+        }
+        let mut result = Ok(());
+        for index in 0..self.drivers.len() {
+            if !self.drivers[index].dirty {
+                continue;
+            }
+            for attempt in 0..LED_DRAW_ATTEMPTS {
+                match self.drivers[index].draw(&mut self.i2c) {
+                    Ok(()) => {
+                        self.drivers[index].dirty = false;
+                        break;
+                    }
+                    Err(e) => {
+                        if attempt + 1 == LED_DRAW_ATTEMPTS {
+                            result = result.and(Err(e));
+                        } else {
+                            self.recover_bus()?;
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Release a device holding SDA low by clocking SCL until it lets go, then send a STOP.
+    /// The I2C peripheral is reset. Fails with [LedError::Stuck] if SDA is still low.
+    pub fn recover_bus(&mut self) -> LedResult<()> {
+        const SCL: u32 = 8;
+        const SDA: u32 = 9;
+        let gpiob = unsafe { &*hal::stm32::GPIOB::ptr() };
+        let regs = i2c_regs();
+        let set = |pin: u32, high: bool| {
+            let bit = if high { 1 << pin } else { 1 << (pin + 16) };
+            gpiob.bsrr.write(|w| unsafe { w.bits(bit) });
+            //~100kHz
+            crate::delay_us(5);
+        };
+        let sda_high = || gpiob.idr.read().bits() & (1 << SDA) != 0;
+
+        regs.cr1.modify(|_, w| w.pe().clear_bit());
+
+        //take over the open drain pins as outputs
+        set(SCL, true);
+        set(SDA, true);
+        gpiob.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b1111 << (SCL * 2))) | (0b0101 << (SCL * 2)))
+        });
+
+        for _ in 0..9 {
+            if sda_high() {
+                break;
+            }
+            set(SCL, false);
+            set(SCL, true);
+        }
+        //STOP, SDA rises while SCL is high
+        set(SCL, false);
+        set(SDA, false);
+        set(SCL, true);
+        set(SDA, true);
+        let released = sda_high();
+
+        //back to the I2C alternate function
+        gpiob.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b1111 << (SCL * 2))) | (0b1010 << (SCL * 2)))
+        });
+        regs.cr1.modify(|_, w| w.pe().set_bit());
+
+        if released {
+            Ok(())
+        } else {
+            Err(LedError::Stuck)
+        }
+    }
+
+    /// Take the error from the last interrupt driven draw, if any.
+    pub fn take_error(&mut self) -> Option<LedError> {
+        self.error.take()
+    }
+
+    /// Start sending the drivers that changed since they were last drawn, in the background.
+    /// The transfer is driven by [FieldLeds::handle_interrupt].
+    /// Returns false if there was nothing to send or a draw is already in progress.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// #[task(binds = I2C1_EV, shared = [field_leds])]
+    /// fn leds_event(mut ctx: leds_event::Context) {
+    ///     ctx.shared.field_leds.lock(|leds| leds.handle_interrupt());
+    /// }
+    ///
+    /// #[task(binds = I2C1_ER, shared = [field_leds])]
+    /// fn leds_error(mut ctx: leds_error::Context) {
+    ///     ctx.shared.field_leds.lock(|leds| leds.handle_interrupt());
+    /// }
+    /// ```
+    pub fn start_draw(&mut self) -> bool {
+        if self.transfer.is_some() {
+            return false;
+        }
+        //recover from a failed draw before starting another
+        if self.error.is_some() || i2c_regs().isr.read().busy().bit_is_set() {
+            if let Err(e) = self.recover_bus() {
+                self.error = Some(e);
+                return false;
+            }
+        }
+        //configuring is short, so it is done blocking
+        for driver in self.drivers.iter_mut() {
+            if driver.dirty && !driver.configured {
+                if let Err(e) = driver.configure(&mut self.i2c) {
+                    self.error = Some(e);
+                }
+            }
+        }
+        self.start_next(0)
+    }
+
+    /// If an interrupt driven draw is in progress.
+    pub fn is_drawing(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    fn start_next(&mut self, first: usize) -> bool {
+        let regs = i2c_regs();
+        match (first..self.drivers.len())
+            .find(|i| self.drivers[*i].dirty && self.drivers[*i].configured)
+        {
+            Some(index) => {
+                let driver = &mut self.drivers[index];
+                //cleared now so changes made during the transfer are sent next time
+                driver.dirty = false;
+                let addr = (driver.pca.addr() as u16) << 1;
+                let len = driver.bytes().len() as u8;
+                self.transfer = Some(LedTransfer {
+                    driver: index,
+                    sent: 0,
+                });
+
+                regs.icr.write(|w| w.stopcf().set_bit().nackcf().set_bit());
+                regs.cr1.modify(|_, w| {
+                    w.txie()
+                        .set_bit()
+                        .nackie()
+                        .set_bit()
+                        .stopie()
+                        .set_bit()
+                        .errie()
+                        .set_bit()
+                });
+                regs.cr2.write(|w| {
+                    w.sadd()
+                        .bits(addr)
+                        .rd_wrn()
+                        .clear_bit()
+                        .nbytes()
+                        .bits(len)
+                        .autoend()
+                        .set_bit()
+                        .start()
+                        .set_bit()
+                });
+                true
+            }
+            None => {
+                self.transfer = None;
+                regs.cr1.modify(|_, w| {
+                    w.txie()
+                        .clear_bit()
+                        .nackie()
+                        .clear_bit()
+                        .stopie()
+                        .clear_bit()
+                        .errie()
+                        .clear_bit()
+                });
+                false
+            }
+        }
+    }
+
+    /// Handle the I2C1 event and error interrupts during an interrupt driven draw.
+    /// A driver that fails to send is marked as changed so it is sent again on the next draw,
+    /// the error is available from [FieldLeds::take_error].
+    pub fn handle_interrupt(&mut self) {
+        let regs = i2c_regs();
+        let isr = regs.isr.read();
+        let transfer = match self.transfer.as_mut() {
+            Some(transfer) => transfer,
+            None => {
+                self.start_next(self.drivers.len());
+                return;
+            }
+        };
+        let driver = &mut self.drivers[transfer.driver];
+
+        if isr.txis().bit_is_set() {
+            let bytes = driver.bytes();
+            let byte = bytes[transfer.sent.min(bytes.len() - 1)];
+            regs.txdr.write(|w| w.txdata().bits(byte));
+            transfer.sent += 1;
+        }
+
+        //a STOP is generated automatically after a NACK
+        if isr.nackf().bit_is_set() {
+            regs.icr.write(|w| w.nackcf().set_bit());
+            driver.dirty = true;
+            driver.configured = false;
+            self.error = Some(LedError::Nack(driver.pca.addr()));
+        }
+
+        if isr.berr().bit_is_set() || isr.arlo().bit_is_set() {
+            regs.icr.write(|w| w.berrcf().set_bit().arlocf().set_bit());
+            driver.dirty = true;
+            self.error = Some(LedError::Bus);
+            self.start_next(self.drivers.len());
+        } else if isr.stopf().bit_is_set() {
+            regs.icr.write(|w| w.stopcf().set_bit());
+            let next = transfer.driver + 1;
+            self.start_next(next);
         }
     }
 }
@@ -252,6 +542,12 @@ impl Field {
         gate_in: hal::gpio::gpiob::PB12<Analog>,
         gate_out: hal::gpio::gpioc::PC0<Analog>,
 
+        //cv outputs
+        cv_out_pins: FieldCvOutPins,
+
+        //knobs and cv inputs
+        control_pins: FieldControlPins,
+
         //midi
         midi_tx: crate::gpio::Daisy13<Analog>,
         midi_rx: crate::gpio::Daisy14<Analog>,
@@ -281,25 +577,26 @@ impl Field {
             oled_spi_rec,
             &clocks,
         );
-        let mut display: FieldDisplay = ssd1309::Builder::new()
-            .connect(display_interface_spi::SPIInterface::new(
-                oled_spi,
-                oled_cmd.into_push_pull_output(),
-                oled_nss.into_push_pull_output(),
-            ))
-            .into();
+        let display = (
+            oled_spi,
+            oled_cmd.into_push_pull_output(),
+            oled_nss.into_push_pull_output(),
+        );
 
-        let mut reset: ssd1309::builder::NoOutputPin<()> = ssd1309::builder::NoOutputPin::new();
-        display.reset(&mut reset, delay).unwrap();
-        display.init().unwrap();
-        display.flush().unwrap();
+        let (mut cv1, mut cv2) = crate::dac::init(
+            cv_out_pins.dac,
+            cv_out_pins.dac_rec,
+            cv_out_pins.out1,
+            cv_out_pins.out2,
+            delay,
+        );
+        cv1.set_calibration(DacCalibration::full_scale(FIELD_CV_OUT_VOLTS));
+        cv2.set_calibration(DacCalibration::full_scale(FIELD_CV_OUT_VOLTS));
 
         let serial_midi = usart1_d
             .serial(
                 (midi_tx.into_alternate_af7(), midi_rx.into_alternate_af7()),
-                stm32h7xx_hal::serial::config::Config::default()
-                    .baudrate(31_250.bps())
-                    .parity_none(),
+                crate::midi::serial_config(),
                 usart1_p,
                 &clocks,
             )
@@ -317,6 +614,8 @@ impl Field {
                 gate_in.into_floating_input(),
                 gate_out.into_push_pull_output(),
             )),
+            cv_out: Some((cv1, cv2)),
+            controls: Some(FieldControls::new(control_pins)),
             display: Some(display),
             serial_midi: Some(serial_midi),
         }
@@ -359,6 +658,22 @@ impl Field {
         self.gates.take().unwrap()
     }
 
+    /// Get the CV outputs tuple.
+    ///
+    /// # Panics
+    /// Will panic if done more than once.
+    pub fn split_cv_out(&mut self) -> FieldCvOut {
+        self.cv_out.take().unwrap()
+    }
+
+    /// Get the knobs and CV inputs.
+    ///
+    /// # Panics
+    /// Will panic if done more than once.
+    pub fn split_controls(&mut self) -> FieldControls {
+        self.controls.take().unwrap()
+    }
+
     /// Get the serial MIDI device.
     ///
     /// # Panics
@@ -372,13 +687,505 @@ impl Field {
     /// # Panics
     /// Will panic if done more than once.
     pub fn split_display(&mut self) -> FieldDisplay {
-        self.display.take().unwrap()
+        let (spi, dc, cs) = self.display.take().unwrap();
+        let mut display = crate::display::ssd1309_spi(spi, dc, cs);
+        //the reset pin isn't connected, this only waits for the supply
+        let mut reset: ssd1309::builder::NoOutputPin<()> = ssd1309::builder::NoOutputPin::new();
+        display.reset(&mut reset, &mut FieldDisplayDelay).unwrap();
+        display.init().unwrap();
+        display.flush().unwrap();
+        display
+    }
+
+    /// Get the display, flushed by DMA on DMA2 stream 7.
+    ///
+    /// # Panics
+    /// Will panic if done more than once, including [Field::split_display].
+    pub fn split_display_dma(
+        &mut self,
+        stream: hal::dma::dma::Stream7<hal::stm32::DMA2>,
+    ) -> FieldDisplayDma {
+        let (spi, dc, cs) = self.display.take().unwrap();
+        FieldDisplayDma::new(spi, dc, cs, stream)
     }
 }
 
+const DISPLAY_BUFFER_SIZE: usize = FIELD_DISPLAY_SIZE.0 * FIELD_DISPLAY_SIZE.1 / 8;
+
+#[link_section = ".sram1_bss"]
+#[no_mangle]
+static mut DISPLAY_DMA_BUFFER: [u8; DISPLAY_BUFFER_SIZE] = [0; DISPLAY_BUFFER_SIZE];
+
+// DMAMUX1 request line for SPI1 TX, DMA2 streams are DMAMUX1 channels 8..16
+const SPI1_TX_DMA: u32 = 38;
+const DMAMUX_CHANNEL: usize = 8 + 7;
+
+// DMA stream control register bits
+const DMA_CR_EN: u32 = 1 << 0;
+const DMA_CR_DIR_M2P: u32 = 0b01 << 6;
+const DMA_CR_MINC: u32 = 1 << 10;
+const DMA_CR_PL_MEDIUM: u32 = 0b01 << 16;
+// DMA2 HIFCR flags for stream 7
+const DMA_STREAM7_FLAGS: u32 = (1 << 22) | (0b1111 << 24);
+
+// SSD1309 commands
+const OLED_INIT: &[u8] = &[
+    0xAE, // display off
+    0xD5, 0x80, // clock divide
+    0xA8, 0x3F, // multiplex 64
+    0xD3, 0x00, // display offset
+    0x40, // start line
+    0x20, 0x00, // horizontal addressing
+    0xA1, // segment remap
+    0xC8, // reverse COM scan
+    0xDA, 0x12, // COM pins
+    0x81, 0x8F, // contrast
+    0xD9, 0xF1, // precharge
+    0xDB, 0x40, // VCOMH deselect
+    0xA4, // display RAM
+    0xA6, // not inverted
+    0xAF, // display on
+];
+const OLED_COLUMN_ADDR: u8 = 0x21;
+const OLED_PAGE_ADDR: u8 = 0x22;
+
+// Inclusive columns and pages that changed since the last flush
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DirtyRegion {
+    x0: u8,
+    x1: u8,
+    page0: u8,
+    page1: u8,
+}
+
+impl DirtyRegion {
+    fn full() -> Self {
+        Self {
+            x0: 0,
+            x1: (FIELD_DISPLAY_SIZE.0 - 1) as u8,
+            page0: 0,
+            page1: (FIELD_DISPLAY_SIZE.1 / 8 - 1) as u8,
+        }
+    }
+
+    fn add(&mut self, x: u8, page: u8) {
+        self.x0 = self.x0.min(x);
+        self.x1 = self.x1.max(x);
+        self.page0 = self.page0.min(page);
+        self.page1 = self.page1.max(page);
+    }
+}
+
+struct FieldDisplayDelay;
+
+impl hal::hal::blocking::delay::DelayMs<u8> for FieldDisplayDelay {
+    fn delay_ms(&mut self, ms: u8) {
+        crate::delay_ms(ms as u32);
+    }
+}
+
+fn spi_regs() -> &'static hal::stm32::spi1::RegisterBlock {
+    unsafe { &*hal::stm32::SPI1::ptr() }
+}
+
+fn dma2_regs() -> &'static hal::stm32::dma1::RegisterBlock {
+    unsafe { &*hal::stm32::DMA2::ptr() }
+}
+
+/// The OLED display, drawn into a framebuffer and flushed in the background by DMA.
+///
+/// Only the region that changed since the last flush is sent. Drawing can continue while a
+/// flush is in progress, a flush requested meanwhile starts when the current one ends.
+/// Call [FieldDisplayDma::handle_interrupt] from the SPI1 interrupt, or poll it.
+pub struct FieldDisplayDma {
+    spi: FieldDisplaySpi,
+    dc: FieldDisplayDc,
+    cs: FieldDisplayCs,
+    stream: hal::dma::dma::Stream7<hal::stm32::DMA2>,
+    framebuffer: [u8; DISPLAY_BUFFER_SIZE],
+    dirty: Option<DirtyRegion>,
+    flushing: bool,
+    pending: bool,
+}
+
+impl FieldDisplayDma {
+    fn new(
+        spi: FieldDisplaySpi,
+        dc: FieldDisplayDc,
+        cs: FieldDisplayCs,
+        stream: hal::dma::dma::Stream7<hal::stm32::DMA2>,
+    ) -> Self {
+        let mut display = Self {
+            spi,
+            dc,
+            cs,
+            stream,
+            framebuffer: [0; DISPLAY_BUFFER_SIZE],
+            dirty: Some(DirtyRegion::full()),
+            flushing: false,
+            pending: false,
+        };
+        display.command(OLED_INIT);
+
+        let dma = dma2_regs();
+        dma.st[7].cr.write(|w| unsafe { w.bits(0) });
+        while dma.st[7].cr.read().bits() & DMA_CR_EN != 0 {}
+        dma.hifcr.write(|w| unsafe { w.bits(DMA_STREAM7_FLAGS) });
+        let dmamux = unsafe { &*hal::stm32::DMAMUX1::ptr() };
+        dmamux.ccr[DMAMUX_CHANNEL].write(|w| unsafe { w.bits(SPI1_TX_DMA) });
+        dma.st[7]
+            .par
+            .write(|w| unsafe { w.bits(&spi_regs().txdr as *const _ as u32) });
+        dma.st[7]
+            .m0ar
+            .write(|w| unsafe { w.bits(DISPLAY_DMA_BUFFER.as_ptr() as u32) });
+        //direct mode
+        dma.st[7].fcr.write(|w| unsafe { w.bits(0) });
+
+        display.flush();
+        display
+    }
+
+    // Blocking command write, only while no DMA flush is in progress
+    fn command(&mut self, bytes: &[u8]) {
+        self.cs.set_low().unwrap();
+        self.dc.set_low().unwrap();
+        self.spi.write(bytes).unwrap();
+        self.cs.set_high().unwrap();
+    }
+
+    /// Set a pixel in the framebuffer.
+    pub fn set_pixel(&mut self, x: u32, y: u32, on: bool) {
+        if x as usize >= FIELD_DISPLAY_SIZE.0 || y as usize >= FIELD_DISPLAY_SIZE.1 {
+            return;
+        }
+        let page = y / 8;
+        let index = page as usize * FIELD_DISPLAY_SIZE.0 + x as usize;
+        let bit = 1 << (y % 8);
+        let byte = if on {
+            self.framebuffer[index] | bit
+        } else {
+            self.framebuffer[index] & !bit
+        };
+        if byte != self.framebuffer[index] {
+            self.framebuffer[index] = byte;
+            match self.dirty.as_mut() {
+                Some(dirty) => dirty.add(x as u8, page as u8),
+                None => {
+                    self.dirty = Some(DirtyRegion {
+                        x0: x as u8,
+                        x1: x as u8,
+                        page0: page as u8,
+                        page1: page as u8,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Send the whole framebuffer on the next flush.
+    pub fn invalidate(&mut self) {
+        self.dirty = Some(DirtyRegion::full());
+    }
+
+    /// Returns true if there are changes that have not been flushed.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.is_some()
+    }
+
+    /// Returns true while a flush is in progress.
+    pub fn is_flushing(&self) -> bool {
+        self.flushing
+    }
+
+    /// Start sending the changed region, without waiting for it to be sent.
+    /// If a flush is in progress this one starts when it ends.
+    pub fn flush(&mut self) {
+        if self.flushing {
+            self.pending = true;
+            return;
+        }
+        self.pending = false;
+        let dirty = match self.dirty.take() {
+            Some(dirty) => dirty,
+            None => return,
+        };
+
+        //copy the region so drawing can continue during the transfer
+        let width = (dirty.x1 - dirty.x0) as usize + 1;
+        let mut len = 0;
+        let dma_buffer = unsafe { &mut DISPLAY_DMA_BUFFER };
+        for page in dirty.page0..=dirty.page1 {
+            let start = page as usize * FIELD_DISPLAY_SIZE.0 + dirty.x0 as usize;
+            dma_buffer[len..len + width].copy_from_slice(&self.framebuffer[start..start + width]);
+            len += width;
+        }
+        //the buffer may be cached if the MPU hasn't made it uncacheable, e.g. without audio
+        unsafe { cortex_m::Peripherals::steal() }
+            .SCB
+            .clean_dcache_by_slice(&dma_buffer[..len]);
+
+        self.command(&[
+            OLED_COLUMN_ADDR,
+            dirty.x0,
+            dirty.x1,
+            OLED_PAGE_ADDR,
+            dirty.page0,
+            dirty.page1,
+        ]);
+        self.flushing = true;
+        self.cs.set_low().unwrap();
+        self.dc.set_high().unwrap();
+
+        let spi = spi_regs();
+        let dma = dma2_regs();
+        //the transfer size can only be set while the SPI is disabled
+        spi.cr1.modify(|_, w| w.spe().clear_bit());
+        spi.cr2.modify(|_, w| w.tsize().bits(len as u16));
+        spi.cfg1.modify(|_, w| w.txdmaen().set_bit());
+        spi.ier.modify(|_, w| w.eotie().set_bit());
+        dma.hifcr.write(|w| unsafe { w.bits(DMA_STREAM7_FLAGS) });
+        dma.st[7].ndtr.write(|w| unsafe { w.bits(len as u32) });
+        dma.st[7].cr.write(|w| unsafe {
+            w.bits(DMA_CR_DIR_M2P | DMA_CR_MINC | DMA_CR_PL_MEDIUM | DMA_CR_EN)
+        });
+        spi.cr1.modify(|_, w| w.spe().set_bit());
+        spi.cr1.modify(|_, w| w.cstart().set_bit());
+    }
+
+    /// Finish a flush once the SPI has sent it, starting a pending one.
+    /// Call from the SPI1 interrupt, or poll it.
+    pub fn handle_interrupt(&mut self) {
+        let spi = spi_regs();
+        if !self.flushing || spi.sr.read().eot().bit_is_clear() {
+            return;
+        }
+        //the transfer is full duplex, drop what was received so it doesn't overrun
+        while spi.sr.read().rxp().bit_is_set() {
+            let _ = unsafe { core::ptr::read_volatile(&spi.rxdr as *const _ as *const u8) };
+        }
+        spi.ifcr.write(|w| {
+            w.eotc()
+                .set_bit()
+                .txtfc()
+                .set_bit()
+                .udrc()
+                .set_bit()
+                .ovrc()
+                .set_bit()
+                .modfc()
+                .set_bit()
+                .tifrec()
+                .set_bit()
+                .suspc()
+                .set_bit()
+        });
+        //back to how the blocking driver expects it, which starts the SPI once at init
+        spi.cr1.modify(|_, w| w.spe().clear_bit());
+        spi.ier.modify(|_, w| w.eotie().clear_bit());
+        spi.cfg1.modify(|_, w| w.txdmaen().clear_bit());
+        spi.cr2.modify(|_, w| w.tsize().bits(0));
+        spi.cr1.modify(|_, w| w.spe().set_bit());
+        spi.cr1.modify(|_, w| w.cstart().set_bit());
+        dma2_regs().st[7].cr.write(|w| unsafe { w.bits(0) });
+
+        self.cs.set_high().unwrap();
+        self.flushing = false;
+        if self.pending {
+            self.flush();
+        }
+    }
+
+    /// Flush and wait until everything has been sent.
+    pub fn flush_blocking(&mut self) {
+        while self.flushing || self.dirty.is_some() {
+            self.flush();
+            self.handle_interrupt();
+        }
+    }
+
+    /// Release the SPI, pins and DMA stream.
+    pub fn free(
+        mut self,
+    ) -> (
+        FieldDisplaySpi,
+        FieldDisplayDc,
+        FieldDisplayCs,
+        hal::dma::dma::Stream7<hal::stm32::DMA2>,
+    ) {
+        while self.flushing {
+            self.handle_interrupt();
+        }
+        (self.spi, self.dc, self.cs, self.stream)
+    }
+}
+
+impl OriginDimensions for FieldDisplayDma {
+    fn size(&self) -> Size {
+        Size::new(FIELD_DISPLAY_SIZE.0 as u32, FIELD_DISPLAY_SIZE.1 as u32)
+    }
+}
+
+impl DrawTarget for FieldDisplayDma {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x >= 0 && point.y >= 0 {
+                self.set_pixel(point.x as u32, point.y as u32, color.is_on());
+            }
+        }
+        Ok(())
+    }
+}
+
+//CD4051 select lines A, B and C
+type KnobMuxSelect = (
+    Daisy21<Output<PushPull>>,
+    Daisy20<Output<PushPull>>,
+    Daisy19<Output<PushPull>>,
+);
+
+/// The 8 knobs, multiplexed by a CD4051 into a single ADC input, and the 4 CV inputs.
+/// Each [FieldControls::update] reads one knob and all of the CV inputs.
+pub struct FieldControls {
+    adc: hal::adc::Adc<hal::stm32::ADC1, hal::adc::Enabled>,
+    knob_mux: Daisy16<Analog>,
+    mux_select: KnobMuxSelect,
+    cv_pins: (
+        Daisy17<Analog>,
+        Daisy18<Analog>,
+        Daisy25<Analog>,
+        Daisy24<Analog>,
+    ),
+    mux_index: usize,
+    knobs: [AnalogControl<()>; FIELD_KNOB_COUNT],
+    cv: [AnalogControl<()>; FIELD_CV_IN_COUNT],
+}
+
+impl FieldControls {
+    pub fn new(pins: FieldControlPins) -> Self {
+        let FieldControlPins {
+            adc,
+            knob_mux,
+            mux_select,
+            cv_in: cv_pins,
+        } = pins;
+        let mut adc = adc.enable();
+        adc.set_resolution(hal::adc::Resolution::SIXTEENBIT);
+        let scale = adc.max_sample() as f32;
+
+        let mut controls = Self {
+            adc,
+            knob_mux,
+            mux_select: (
+                mux_select.0.into_push_pull_output(),
+                mux_select.1.into_push_pull_output(),
+                mux_select.2.into_push_pull_output(),
+            ),
+            cv_pins,
+            mux_index: 0,
+            knobs: [(); FIELD_KNOB_COUNT].map(|_| AnalogControl::new((), scale)),
+            cv: [(); FIELD_CV_IN_COUNT].map(|_| {
+                let mut control = AnalogControl::new((), scale);
+                //the cv input circuits invert
+                control.set_cv_calibration(CvCalibration::bipolar(5.0, true));
+                control
+            }),
+        };
+        controls.select(0);
+        controls
+    }
+
+    fn select(&mut self, index: usize) {
+        fn set<P: OutputPin>(pin: &mut P, high: bool) {
+            if high {
+                pin.set_high().ok().unwrap();
+            } else {
+                pin.set_low().ok().unwrap();
+            }
+        }
+        self.mux_index = index;
+        set(&mut self.mux_select.0, index & 0b001 != 0);
+        set(&mut self.mux_select.1, index & 0b010 != 0);
+        set(&mut self.mux_select.2, index & 0b100 != 0);
+    }
+
+    /// Read the selected knob and all the CV inputs, then select the next knob so the mux
+    /// has settled by the next update. This should be called on a timer, all knobs are
+    /// updated at 1/8th of the rate.
+    pub fn update(&mut self) {
+        if let Ok(data) = self.adc.read(&mut self.knob_mux) {
+            self.knobs[self.mux_index].update(data);
+        }
+        self.select((self.mux_index + 1) % FIELD_KNOB_COUNT);
+
+        let readings: [Result<u32, _>; FIELD_CV_IN_COUNT] = [
+            self.adc.read(&mut self.cv_pins.0),
+            self.adc.read(&mut self.cv_pins.1),
+            self.adc.read(&mut self.cv_pins.2),
+            self.adc.read(&mut self.cv_pins.3),
+        ];
+        for (control, reading) in self.cv.iter_mut().zip(readings.iter()) {
+            if let Ok(data) = reading {
+                control.update(*data);
+            }
+        }
+    }
+
+    /// Get the knob at `index` (0..8).
+    pub fn knob(&self, index: usize) -> &AnalogControl<()> {
+        &self.knobs[index]
+    }
+
+    /// Get the knob at `index` (0..8) mutably, e.g. to set its filter or transform.
+    pub fn knob_mut(&mut self, index: usize) -> &mut AnalogControl<()> {
+        &mut self.knobs[index]
+    }
+
+    /// Get all the knobs.
+    pub fn knobs(&mut self) -> &mut [AnalogControl<()>; FIELD_KNOB_COUNT] {
+        &mut self.knobs
+    }
+
+    /// Get the CV input at `index` (0..4).
+    pub fn cv(&self, index: usize) -> &AnalogControl<()> {
+        &self.cv[index]
+    }
+
+    /// Get the CV input at `index` (0..4) mutably, e.g. to set its calibration.
+    pub fn cv_mut(&mut self, index: usize) -> &mut AnalogControl<()> {
+        &mut self.cv[index]
+    }
+
+    /// Get all the CV inputs.
+    pub fn cv_inputs(&mut self) -> &mut [AnalogControl<()>; FIELD_CV_IN_COUNT] {
+        &mut self.cv
+    }
+
+    /// Get the ADC, e.g. to change its resolution or sample time.
+    pub fn adc(&mut self) -> &mut hal::adc::Adc<hal::stm32::ADC1, hal::adc::Enabled> {
+        &mut self.adc
+    }
+}
+
+/// Number of keys on the keyboard.
+pub const FIELD_KEY_COUNT: usize = 16;
+
 struct FieldShiftDelay;
+
+/// The 16 key keyboard, debounced like [Switch](crate::hid::Switch).
+/// Key indices count from the left of the top row (0..8) then the bottom row (8..16).
 pub struct FieldKeyboard {
     sreg: ShiftKeyboard,
+    debouncers: [Debouncer<u8, Repeat4>; FIELD_KEY_COUNT],
+    state: [u8; 2],
+    previous: [u8; 2],
+    held: Vec<u8, FIELD_KEY_COUNT>,
 }
 
 type ShiftKeyboard = ShiftIn<
@@ -400,7 +1207,13 @@ impl FieldKeyboard {
         let data = data.into_floating_input();
         let sreg = ShiftIn::new(latch, clock, data, FieldShiftDelay);
 
-        Self { sreg }
+        Self {
+            sreg,
+            debouncers: [(); FIELD_KEY_COUNT].map(|_| debounce_4(false)),
+            state: [0; 2],
+            previous: [0; 2],
+            held: Vec::new(),
+        }
     }
 
     /// Read in all the data
@@ -422,6 +1235,103 @@ impl FieldKeyboard {
         }
         o
     }
+
+    /// Read and debounce the keyboard, keeping track of the changes since the previous update.
+    /// This should be called on a timer, a key changes after 4 consistent reads.
+    pub fn update(&mut self) {
+        let raw = self.read();
+        self.previous = self.state;
+        for (index, debouncer) in self.debouncers.iter_mut().enumerate() {
+            let (by, bit) = (index / 8, 1 << (index % 8));
+            match debouncer.update(raw[by] & bit != 0) {
+                Some(Edge::Rising) => {
+                    self.state[by] |= bit;
+                    //can't overflow, each key is only held once
+                    self.held.push(index as u8).ok();
+                }
+                Some(Edge::Falling) => {
+                    self.state[by] &= !bit;
+                    self.held.retain(|key| *key as usize != index);
+                }
+                None => (),
+            }
+        }
+    }
+
+    /// If the key at `index` (0..16) was down on the last update.
+    pub fn is_pressed(&self, index: usize) -> bool {
+        assert!(index < FIELD_KEY_COUNT);
+        self.state[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    /// If the key at `index` (0..16) was pressed on the last update.
+    pub fn is_rising(&self, index: usize) -> bool {
+        assert!(index < FIELD_KEY_COUNT);
+        let bit = 1 << (index % 8);
+        (self.state[index / 8] & !self.previous[index / 8]) & bit != 0
+    }
+
+    /// If the key at `index` (0..16) was released on the last update.
+    pub fn is_falling(&self, index: usize) -> bool {
+        assert!(index < FIELD_KEY_COUNT);
+        let bit = 1 << (index % 8);
+        (!self.state[index / 8] & self.previous[index / 8]) & bit != 0
+    }
+
+    /// The debounced state of all keys from the last update.
+    pub fn state(&self) -> [u8; 2] {
+        self.state
+    }
+
+    /// The keys currently held down, in the order they were pressed, e.g. for chords.
+    pub fn held_keys(&self) -> &[u8] {
+        &self.held
+    }
+
+    /// Play the keys pressed and released on the last update through `notes`, calling `handler`
+    /// with the resulting note events. The key index is the scale degree.
+    pub fn play_notes<F: FnMut(NoteEvent), const N: usize>(
+        &self,
+        notes: &mut KeyNotes<N>,
+        mut handler: F,
+    ) {
+        for key in 0..FIELD_KEY_COUNT {
+            if self.is_falling(key) {
+                notes.key_released(key, &mut handler);
+            }
+            if self.is_rising(key) {
+                notes.key_pressed(key, &mut handler);
+            }
+        }
+    }
+
+    /// If exactly the keys in `chord` are held down, in any order.
+    pub fn is_chord(&self, chord: &[u8]) -> bool {
+        self.held.len() == chord.len() && chord.iter().all(|key| self.held.contains(key))
+    }
+}
+
+impl EventSource for FieldKeyboard {
+    fn push_events<const N: usize>(&self, id: u8, producer: &mut EventProducer<'_, N>) -> bool {
+        let mut ok = true;
+        for (by, (state, previous)) in self.state.iter().zip(self.previous.iter()).enumerate() {
+            let changed = state ^ previous;
+            for b in 0..8 {
+                if changed & (1 << b) != 0 {
+                    let key = (by * 8 + b) as u8;
+                    ok &= push_event(
+                        producer,
+                        if state & (1 << b) != 0 {
+                            HidEvent::KeyPressed(id, key)
+                        } else {
+                            HidEvent::KeyReleased(id, key)
+                        },
+                    );
+                }
+            }
+        }
+        ok
+    }
 }
 
 impl ShiftClockDelay for FieldShiftDelay {
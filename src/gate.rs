@@ -0,0 +1,647 @@
+//! Gate and trigger inputs and outputs.
+//! Input timestamps are in CPU cycles from the DWT cycle counter, enabled by
+//! [System::init_debug](crate::system::System::init_debug).
+use stm32h7xx_hal::gpio::{Edge, ExtiPin};
+use stm32h7xx_hal::hal::digital::v2::{InputPin, OutputPin};
+use stm32h7xx_hal::rcc::{rec, CoreClocks, ResetEnable};
+use stm32h7xx_hal::stm32;
+use stm32h7xx_hal::time::Hertz;
+
+use crate::*;
+
+/// Number of clock intervals averaged for the tempo estimate.
+const INTERVAL_ARR_SIZE: usize = 4;
+
+/// Default length of a trigger from [GateOut::trigger] in milliseconds.
+pub const DEFAULT_TRIGGER_MS: u32 = 5;
+
+/// Get the current timestamp in CPU cycles, wraps around every ~8.9 seconds.
+pub fn now() -> u32 {
+    cortex_m::peripheral::DWT::cycle_count()
+}
+
+/// Convert a duration in CPU cycles to microseconds.
+pub fn cycles_to_us(cycles: u32) -> u32 {
+    cycles / MICROCYCLES
+}
+
+/// Gate input driven by an EXTI interrupt on both edges.
+/// Records edge timestamps, reports triggers and estimates the rate of an incoming clock.
+pub struct GateIn<P> {
+    pin: P,
+    //EXTI line bit of the pin
+    line: u32,
+    invert: bool,
+    state: bool,
+    last_rise: Option<u32>,
+    last_fall: Option<u32>,
+    intervals: [u32; INTERVAL_ARR_SIZE],
+    interval_count: usize,
+    index: usize,
+    triggers: u32,
+    releases: u32,
+}
+
+impl<P> GateIn<P>
+where
+    P: ExtiPin + InputPin,
+    <P as InputPin>::Error: core::fmt::Debug,
+{
+    /// Create a new gate input and enable its interrupt on both edges.
+    /// Daisy gate input circuits are usually inverting, set `invert` for those.
+    ///
+    /// # Remarks
+    /// The EXTI interrupt for the pin still needs to be unmasked, e.g. by binding an RTIC task
+    /// to it, which calls [GateIn::handle_interrupt].
+    pub fn new(
+        mut pin: P,
+        invert: bool,
+        syscfg: &mut stm32::SYSCFG,
+        exti: &mut stm32::EXTI,
+    ) -> Self {
+        pin.make_interrupt_source(syscfg);
+        pin.trigger_on_edge(exti, Edge::RisingFalling);
+        //the HAL doesn't expose the pin's EXTI line, take it from the mask bit it sets
+        pin.disable_interrupt(exti);
+        let masked = exti.cpuimr1.read().bits();
+        pin.enable_interrupt(exti);
+        let line = exti.cpuimr1.read().bits() & !masked;
+
+        let mut gate = Self {
+            pin,
+            line,
+            invert,
+            state: false,
+            last_rise: None,
+            last_fall: None,
+            intervals: [0; INTERVAL_ARR_SIZE],
+            interval_count: 0,
+            index: 0,
+            triggers: 0,
+            releases: 0,
+        };
+        gate.state = gate.read();
+        gate
+    }
+
+    /// Read the current gate state directly from the pin.
+    pub fn read(&self) -> bool {
+        self.pin.is_high().unwrap() != self.invert
+    }
+
+    /// If the interrupt for this gate is pending, useful when an EXTI line is shared.
+    pub fn is_pending(&self) -> bool {
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        exti.cpupr1.read().bits() & self.line != 0
+    }
+
+    /// Handle the EXTI interrupt, timestamped with [now].
+    pub fn handle_interrupt(&mut self) {
+        self.handle_interrupt_at(now());
+    }
+
+    /// Handle the EXTI interrupt with a timestamp in CPU cycles.
+    pub fn handle_interrupt_at(&mut self, timestamp: u32) {
+        self.pin.clear_interrupt_pending_bit();
+
+        let state = self.read();
+        // Ignore glitches shorter than the interrupt latency
+        if state == self.state {
+            return;
+        }
+        self.state = state;
+
+        if state {
+            if let Some(last_rise) = self.last_rise {
+                self.add_interval(timestamp.wrapping_sub(last_rise));
+            }
+            self.last_rise = Some(timestamp);
+            self.triggers = self.triggers.saturating_add(1);
+        } else {
+            self.last_fall = Some(timestamp);
+            self.releases = self.releases.saturating_add(1);
+        }
+    }
+
+    fn add_interval(&mut self, interval: u32) {
+        // Restart the estimate when the tempo jumps, e.g. the clock was stopped
+        if let Some(period) = self.period() {
+            if interval > period.saturating_mul(2) || interval < period / 2 {
+                self.interval_count = 0;
+            }
+        }
+        if self.interval_count == 0 {
+            self.index = 0;
+        }
+        self.intervals[self.index] = interval;
+        self.index = (self.index + 1) % INTERVAL_ARR_SIZE;
+        self.interval_count = (self.interval_count + 1).min(INTERVAL_ARR_SIZE);
+    }
+
+    /// The gate state as of the last edge.
+    pub fn is_high(&self) -> bool {
+        self.state
+    }
+
+    /// Returns true once for every rising edge that hasn't been taken yet.
+    pub fn take_trigger(&mut self) -> bool {
+        let triggered = self.triggers > 0;
+        self.triggers = self.triggers.saturating_sub(1);
+        triggered
+    }
+
+    /// Returns true once for every falling edge that hasn't been taken yet.
+    pub fn take_release(&mut self) -> bool {
+        let released = self.releases > 0;
+        self.releases = self.releases.saturating_sub(1);
+        released
+    }
+
+    /// Take the number of rising edges since the last call.
+    pub fn take_triggers(&mut self) -> u32 {
+        core::mem::replace(&mut self.triggers, 0)
+    }
+
+    /// Take the number of falling edges since the last call.
+    pub fn take_releases(&mut self) -> u32 {
+        core::mem::replace(&mut self.releases, 0)
+    }
+
+    /// Timestamp of the last rising edge in CPU cycles.
+    pub fn last_rise(&self) -> Option<u32> {
+        self.last_rise
+    }
+
+    /// Timestamp of the last falling edge in CPU cycles.
+    pub fn last_fall(&self) -> Option<u32> {
+        self.last_fall
+    }
+
+    /// Length of the last gate in CPU cycles, `None` while the gate is high.
+    pub fn gate_length(&self) -> Option<u32> {
+        match (self.state, self.last_rise, self.last_fall) {
+            (false, Some(rise), Some(fall)) => Some(fall.wrapping_sub(rise)),
+            _ => None,
+        }
+    }
+
+    /// Average time between rising edges in CPU cycles.
+    pub fn period(&self) -> Option<u32> {
+        if self.interval_count == 0 {
+            return None;
+        }
+        let sum: u64 = self.intervals[..self.interval_count]
+            .iter()
+            .map(|i| *i as u64)
+            .sum();
+        Some((sum / self.interval_count as u64) as u32)
+    }
+
+    /// Estimated frequency of the incoming clock in Hz.
+    pub fn frequency(&self) -> Option<f32> {
+        self.period()
+            .filter(|period| *period > 0)
+            .map(|period| CLOCK_RATE_HZ.0 as f32 / period as f32)
+    }
+
+    /// Estimated tempo in beats per minute with `ppqn` clock pulses per quarter note.
+    pub fn bpm(&self, ppqn: u32) -> Option<f32> {
+        self.frequency()
+            .map(|freq| freq * 60.0 / ppqn.max(1) as f32)
+    }
+
+    /// If a clock is running, i.e. the last rising edge was less than 2 periods before `now`.
+    pub fn is_clock_running(&self, now: u32) -> bool {
+        match (self.last_rise, self.period()) {
+            (Some(last_rise), Some(period)) => {
+                now.wrapping_sub(last_rise) < period.saturating_mul(2)
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget all recorded edges and the tempo estimate.
+    pub fn reset(&mut self) {
+        self.last_rise = None;
+        self.last_fall = None;
+        self.interval_count = 0;
+        self.index = 0;
+        self.triggers = 0;
+        self.releases = 0;
+    }
+
+    /// Get the pin associated with this gate.
+    pub fn get_pin(&mut self) -> &mut P {
+        &mut self.pin
+    }
+}
+
+/// Frequency a [GateTimer] counts at, about 20 counts per sample at 48kHz.
+pub const GATE_TIMER_HZ: u32 = 1_000_000;
+
+// Edges of a gate that can fall in one call to GateOut::advance: the end of the current gate,
+// the start of a scheduled one and its end.
+const MAX_EDGES: usize = 3;
+
+/// A one shot timer that lets a [GateOut] change its pin on the exact sample, instead of when
+/// [GateOut::advance] is called.
+pub trait EdgeTimer {
+    /// Frequency the timer counts at.
+    fn tick_hz(&self) -> u32;
+    /// Start a delay of `ticks`, replacing one in progress.
+    fn start(&mut self, ticks: u32);
+    /// Stop a delay in progress.
+    fn cancel(&mut self);
+    /// Clear the interrupt flag, returns true if a delay ended.
+    fn take_elapsed(&mut self) -> bool;
+}
+
+/// For a [GateOut] without a timer.
+pub enum NoTimer {}
+
+impl EdgeTimer for NoTimer {
+    fn tick_hz(&self) -> u32 {
+        match *self {}
+    }
+
+    fn start(&mut self, _ticks: u32) {
+        match *self {}
+    }
+
+    fn cancel(&mut self) {
+        match *self {}
+    }
+
+    fn take_elapsed(&mut self) -> bool {
+        match *self {}
+    }
+}
+
+/// A general purpose or basic timer used as an [EdgeTimer], counting at [GATE_TIMER_HZ].
+///
+/// Its interrupt needs to be bound to a task that calls [GateOut::handle_interrupt].
+///
+/// # Example
+///
+/// ```rust
+/// let timer = GateTimer::tim3(device.TIM3, ccdr.peripheral.TIM3, &ccdr.clocks);
+/// let mut gate_out = GateOut::with_timer(daisy15, false, timer);
+///
+/// // In the audio callback, a trigger on the 10th sample of the block
+/// gate_out.trigger_after(10);
+/// gate_out.advance(audio::BLOCK_SIZE_MAX as u32);
+///
+/// // In a task bound to TIM3
+/// gate_out.handle_interrupt();
+/// ```
+pub struct GateTimer<TIM> {
+    tim: TIM,
+    tick_hz: u32,
+}
+
+impl<TIM> GateTimer<TIM> {
+    /// Release the timer.
+    pub fn free(self) -> TIM {
+        self.tim
+    }
+}
+
+macro_rules! gate_timer {
+    ($($TIM:ident: ($tim:ident, $Rec:ident, $max:expr),)+) => {
+        $(
+            impl GateTimer<stm32::$TIM> {
+                /// Set up the timer as a one shot with its update interrupt enabled.
+                pub fn $tim(tim: stm32::$TIM, prec: rec::$Rec, clocks: &CoreClocks) -> Self {
+                    let _ = prec.enable().reset();
+                    let clock = clocks.timx_ker_ck().0;
+                    let prescaler = (clock / GATE_TIMER_HZ).max(1) - 1;
+
+                    //one pulse, only the counter reaching the end sets the update flag
+                    tim.cr1.write(|w| w.opm().set_bit().urs().set_bit());
+                    tim.psc.write(|w| unsafe { w.bits(prescaler) });
+                    //load the prescaler
+                    tim.egr.write(|w| w.ug().set_bit());
+                    tim.sr.write(|w| unsafe { w.bits(0) });
+                    tim.dier.write(|w| w.uie().set_bit());
+
+                    Self {
+                        tim,
+                        tick_hz: clock / (prescaler + 1),
+                    }
+                }
+            }
+
+            impl EdgeTimer for GateTimer<stm32::$TIM> {
+                fn tick_hz(&self) -> u32 {
+                    self.tick_hz
+                }
+
+                fn start(&mut self, ticks: u32) {
+                    let end: u32 = ticks.saturating_sub(1).clamp(1, $max);
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.cnt.write(|w| unsafe { w.bits(0) });
+                    self.tim.arr.write(|w| unsafe { w.bits(end) });
+                    self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+
+                fn cancel(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                }
+
+                fn take_elapsed(&mut self) -> bool {
+                    let elapsed = self.tim.sr.read().uif().bit_is_set();
+                    if elapsed {
+                        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    }
+                    elapsed
+                }
+            }
+        )+
+    };
+}
+
+gate_timer! {
+    TIM2: (tim2, Tim2, u32::MAX),
+    TIM3: (tim3, Tim3, 0xFFFF),
+    TIM4: (tim4, Tim4, 0xFFFF),
+    TIM5: (tim5, Tim5, u32::MAX),
+    TIM6: (tim6, Tim6, 0xFFFF),
+    TIM7: (tim7, Tim7, 0xFFFF),
+}
+
+/// Gate and trigger output with timing counted in calls to [GateOut::tick] or samples passed
+/// to [GateOut::advance], typically audio samples so it can be driven from the audio callback.
+///
+/// Without a timer the pin only changes when [GateOut::tick] or [GateOut::advance] runs, so
+/// driven from the audio callback the edges land at block resolution. With an [EdgeTimer],
+/// see [GateOut::with_timer], each edge within a block is scheduled on its sample, counted
+/// from the call to [GateOut::advance].
+pub struct GateOut<P, T = NoTimer> {
+    pin: P,
+    invert: bool,
+    rate: Hertz,
+    trigger_length: u32,
+    remaining: u32,
+    scheduled: Option<(u32, u32)>,
+    hold: bool,
+    gate: bool,
+    state: bool,
+    timer: Option<T>,
+    edges: [(u32, bool); MAX_EDGES],
+    edge_count: usize,
+    next_edge: usize,
+}
+
+impl<P> GateOut<P>
+where
+    P: OutputPin,
+{
+    /// Create a new gate output, timed at the audio sample rate. The output starts low.
+    pub fn new(pin: P, invert: bool) -> Self {
+        Self::init(pin, invert, None)
+    }
+}
+
+impl<P, T> GateOut<P, T>
+where
+    P: OutputPin,
+    T: EdgeTimer,
+{
+    /// Create a new gate output that changes the pin on the exact sample using `timer`.
+    /// The output starts low.
+    pub fn with_timer(pin: P, invert: bool, timer: T) -> Self {
+        Self::init(pin, invert, Some(timer))
+    }
+
+    fn init(pin: P, invert: bool, timer: Option<T>) -> Self {
+        let mut gate = Self {
+            pin,
+            invert,
+            rate: AUDIO_SAMPLE_HZ,
+            trigger_length: 0,
+            remaining: 0,
+            scheduled: None,
+            hold: false,
+            gate: false,
+            state: true,
+            timer,
+            edges: [(0, false); MAX_EDGES],
+            edge_count: 0,
+            next_edge: 0,
+        };
+        gate.set_trigger_ms(DEFAULT_TRIGGER_MS);
+        gate.write();
+        gate
+    }
+
+    /// Set how often the output is advanced, used to convert milliseconds.
+    /// Defaults to [AUDIO_SAMPLE_HZ]. Does not rescale the trigger length.
+    pub fn set_rate(&mut self, rate: Hertz) {
+        self.rate = rate;
+    }
+
+    /// Convert milliseconds to samples at the current rate.
+    pub fn ms_to_samples(&self, ms: u32) -> u32 {
+        ((ms as u64 * self.rate.0 as u64) / MILLI as u64) as u32
+    }
+
+    /// Set the length of [GateOut::trigger] in samples.
+    pub fn set_trigger_length(&mut self, samples: u32) {
+        self.trigger_length = samples.max(1);
+    }
+
+    /// Set the length of [GateOut::trigger] in milliseconds.
+    pub fn set_trigger_ms(&mut self, ms: u32) {
+        self.set_trigger_length(self.ms_to_samples(ms));
+    }
+
+    /// Fire a trigger now.
+    pub fn trigger(&mut self) {
+        self.gate_for(self.trigger_length);
+    }
+
+    /// Set the output high now for `samples`, restarting any gate in progress.
+    pub fn gate_for(&mut self, samples: u32) {
+        self.cancel_edges();
+        self.scheduled = None;
+        self.remaining = samples;
+        self.gate = samples > 0;
+        self.write();
+    }
+
+    /// Set the output high now for `ms` milliseconds.
+    pub fn gate_for_ms(&mut self, ms: u32) {
+        self.gate_for(self.ms_to_samples(ms));
+    }
+
+    /// Set the output high for `length` samples, starting after `delay` samples.
+    /// With a timer the pin changes on that sample of the next [GateOut::advance] that reaches
+    /// it, otherwise when that call runs.
+    pub fn gate_after(&mut self, delay: u32, length: u32) {
+        if delay == 0 {
+            self.gate_for(length);
+        } else {
+            self.scheduled = Some((delay, length));
+        }
+    }
+
+    /// Fire a trigger after `delay` samples.
+    pub fn trigger_after(&mut self, delay: u32) {
+        self.gate_after(delay, self.trigger_length);
+    }
+
+    /// Hold the output high until [GateOut::release], timed gates continue underneath.
+    pub fn hold(&mut self) {
+        self.hold = true;
+        self.write();
+    }
+
+    /// Stop holding the output high.
+    pub fn release(&mut self) {
+        self.hold = false;
+        self.write();
+    }
+
+    /// Cancel any hold, timed or scheduled gate and set the output low.
+    pub fn clear(&mut self) {
+        self.cancel_edges();
+        self.hold = false;
+        self.remaining = 0;
+        self.scheduled = None;
+        self.gate = false;
+        self.write();
+    }
+
+    /// Advance by one sample.
+    pub fn tick(&mut self) {
+        self.advance(1);
+    }
+
+    /// Advance by a number of samples, e.g. once per audio block with the block size.
+    pub fn advance(&mut self, samples: u32) {
+        //edges from the last call that are still waiting are late, apply them now
+        while self.next_edge < self.edge_count {
+            self.apply_edge();
+        }
+        self.cancel_edges();
+
+        //when the timed gate changes within this call, in samples from now
+        let mut start = 0;
+        let mut remaining = self.remaining;
+        match self.scheduled {
+            Some((delay, length)) if delay <= samples => {
+                self.scheduled = None;
+                if remaining > 0 && remaining < delay {
+                    self.push_edge(remaining, false);
+                }
+                let high = remaining >= delay && remaining > 0;
+                if high != (length > 0) {
+                    self.push_edge(delay, length > 0);
+                }
+                start = delay;
+                remaining = length;
+            }
+            Some((delay, length)) => self.scheduled = Some((delay - samples, length)),
+            None => (),
+        }
+        if remaining > 0 && start + remaining <= samples {
+            self.push_edge(start + remaining, false);
+        }
+        self.remaining = (start + remaining).saturating_sub(samples);
+
+        if self.timer.is_some() {
+            self.start_edge(0);
+            return;
+        }
+        //without a timer the pin changes now, keep a gate that started and ended within this
+        //call high until the next one
+        let edges = &self.edges[..self.edge_count];
+        let started = edges.iter().any(|(_, high)| *high);
+        match edges.last() {
+            Some((_, false)) if started => {
+                self.remaining = 1;
+                self.gate = true;
+            }
+            Some((_, high)) => self.gate = *high,
+            None => (),
+        }
+        self.edge_count = 0;
+        self.write();
+    }
+
+    /// Change the pin when a scheduled edge is due.
+    /// Call from the timer's interrupt when the gate has an [EdgeTimer].
+    pub fn handle_interrupt(&mut self) {
+        let elapsed = match self.timer.as_mut() {
+            Some(timer) => timer.take_elapsed(),
+            None => false,
+        };
+        if elapsed && self.next_edge < self.edge_count {
+            let from = self.edges[self.next_edge].0;
+            self.apply_edge();
+            self.start_edge(from);
+        }
+    }
+
+    fn push_edge(&mut self, offset: u32, high: bool) {
+        self.edges[self.edge_count] = (offset, high);
+        self.edge_count += 1;
+    }
+
+    fn cancel_edges(&mut self) {
+        if let Some(timer) = self.timer.as_mut() {
+            timer.cancel();
+        }
+        self.edge_count = 0;
+        self.next_edge = 0;
+    }
+
+    fn apply_edge(&mut self) {
+        self.gate = self.edges[self.next_edge].1;
+        self.next_edge += 1;
+        self.write();
+    }
+
+    // Start the timer for the next edge, `from` is the offset of the last one in samples
+    fn start_edge(&mut self, from: u32) {
+        if self.next_edge >= self.edge_count {
+            return;
+        }
+        let samples = self.edges[self.next_edge].0 - from;
+        let rate = self.rate.0 as u64;
+        if let Some(timer) = self.timer.as_mut() {
+            let ticks = samples as u64 * timer.tick_hz() as u64 / rate;
+            timer.start(ticks.min(u32::MAX as u64) as u32);
+        }
+    }
+
+    /// If the output is currently high.
+    pub fn is_high(&self) -> bool {
+        self.state
+    }
+
+    fn write(&mut self) {
+        let state = self.hold || self.gate;
+        if state != self.state {
+            self.state = state;
+            if state != self.invert {
+                self.pin.set_high().ok().unwrap();
+            } else {
+                self.pin.set_low().ok().unwrap();
+            }
+        }
+    }
+
+    /// Get the pin associated with this gate.
+    pub fn get_pin(&mut self) -> &mut P {
+        &mut self.pin
+    }
+
+    /// Release the pin and timer.
+    pub fn free(mut self) -> (P, Option<T>) {
+        self.cancel_edges();
+        (self.pin, self.timer)
+    }
+}
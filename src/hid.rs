@@ -2,28 +2,124 @@
 #[allow(unused_imports)]
 use stm32h7xx_hal::gpio::{Analog, Input, Output, PullDown, PullUp, PushPull};
 use stm32h7xx_hal::hal::digital::v2::{InputPin, OutputPin};
+use stm32h7xx_hal::hal::PwmPin;
+use stm32h7xx_hal::time::Hertz;
 
 use debouncr::{debounce_4, Debouncer, Edge, Repeat4};
+use heapless::spsc::{Consumer, Producer, Queue};
 use micromath::F32Ext;
 
+use crate::MILLI;
+
 /// Define the types for a transformation function for AnalogControl
 pub type TransformFn = fn(f32) -> f32;
 
 /// If the switch is a pull-up or pull-down type
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SwitchType {
     PullUp,
     PullDown,
 }
 
 /// LED blink status
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BlinkStatus {
     Disabled,
     On,
     Off,
 }
 
+/// 8 bit brightness to 12 bit PWM duty gamma correction table.
+pub const GAMMA: [u16; 256] = [
+    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 3, 3, 4, 4, 5, 5, 6, 7, 8, 8, 9, 10,
+    11, 12, 13, 15, 16, 17, 18, 20, 21, 23, 25, 26, 28, 30, 32, 34, 36, 38, 40, 43, 45, 48, 50, 53,
+    56, 59, 62, 65, 68, 71, 75, 78, 82, 85, 89, 93, 97, 101, 105, 110, 114, 119, 123, 128, 133,
+    138, 143, 149, 154, 159, 165, 171, 177, 183, 189, 195, 202, 208, 215, 222, 229, 236, 243, 250,
+    258, 266, 273, 281, 290, 298, 306, 315, 324, 332, 341, 351, 360, 369, 379, 389, 399, 409, 419,
+    430, 440, 451, 462, 473, 485, 496, 508, 520, 532, 544, 556, 569, 582, 594, 608, 621, 634, 648,
+    662, 676, 690, 704, 719, 734, 749, 764, 779, 795, 811, 827, 843, 859, 876, 893, 910, 927, 944,
+    962, 980, 998, 1016, 1034, 1053, 1072, 1091, 1110, 1130, 1150, 1170, 1190, 1210, 1231, 1252,
+    1273, 1294, 1316, 1338, 1360, 1382, 1404, 1427, 1450, 1473, 1497, 1520, 1544, 1568, 1593, 1617,
+    1642, 1667, 1693, 1718, 1744, 1770, 1797, 1823, 1850, 1877, 1905, 1932, 1960, 1988, 2017, 2045,
+    2074, 2103, 2133, 2162, 2192, 2223, 2253, 2284, 2315, 2346, 2378, 2410, 2442, 2474, 2507, 2540,
+    2573, 2606, 2640, 2674, 2708, 2743, 2778, 2813, 2849, 2884, 2920, 2957, 2993, 3030, 3067, 3105,
+    3143, 3181, 3219, 3258, 3297, 3336, 3376, 3416, 3456, 3496, 3537, 3578, 3619, 3661, 3703, 3745,
+    3788, 3831, 3874, 3918, 3962, 4006, 4050, 4095,
+];
+
+/// Gamma correct a brightness from 0.0 to 1.0 using [GAMMA].
+pub fn gamma(value: f32) -> f32 {
+    let index = (value.clamp(0.0, 1.0) * 255.0 + 0.5) as usize;
+    GAMMA[index] as f32 / 4095.0
+}
+
+/// Events reported by controls, the first value is the id given to [EventSource::push_events].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HidEvent {
+    /// A switch was pressed.
+    Pressed(u8),
+    /// A switch was released.
+    Released(u8),
+    /// A switch has been held down for its held threshold.
+    LongPress(u8),
+    /// A switch was released after being held past its held threshold.
+    LongRelease(u8),
+    /// A switch was pressed twice inside its double threshold.
+    DoublePress(u8),
+    /// A switch is still held and its repeat interval elapsed.
+    Repeat(u8),
+    /// A 3 position switch moved.
+    Position(u8, SwitchPosition),
+    /// An encoder turned by the given number of steps, positive is clockwise.
+    Encoder(u8, i32),
+    /// A key on a keyboard was pressed, the second value is the key index.
+    KeyPressed(u8, u8),
+    /// A key on a keyboard was released, the second value is the key index.
+    KeyReleased(u8, u8),
+}
+
+/// Lock-free single producer, single consumer queue of [HidEvent]s, holds `N - 1` events.
+///
+/// # Example
+///
+/// ```rust
+/// // In init, with `queue: EventQueue<32> = EventQueue::new()` as a local resource
+/// let (producer, consumer) = ctx.local.queue.split();
+/// // In the scanning task
+/// switch1.update();
+/// switch1.push_events(0, &mut producer);
+/// // In a lower priority task
+/// while let Some(event) = consumer.dequeue() {}
+/// ```
+pub type EventQueue<const N: usize> = Queue<HidEvent, N>;
+/// Producer end of an [EventQueue].
+pub type EventProducer<'a, const N: usize> = Producer<'a, HidEvent, N>;
+/// Consumer end of an [EventQueue].
+pub type EventConsumer<'a, const N: usize> = Consumer<'a, HidEvent, N>;
+
+/// A control that can report changes from its last update as [HidEvent]s.
+pub trait EventSource {
+    /// Push the events from the last update, `id` identifies this control in the events.
+    /// Returns false if the queue was full and events were dropped.
+    fn push_events<const N: usize>(&self, id: u8, producer: &mut EventProducer<'_, N>) -> bool;
+}
+
+/// Push an event, returns false if the queue is full.
+pub(crate) fn push_event<const N: usize>(
+    producer: &mut EventProducer<'_, N>,
+    event: HidEvent,
+) -> bool {
+    producer.enqueue(event).is_ok()
+}
+
+/// Default rate that [Switch::update] is expected to be called at.
+pub const DEFAULT_UPDATE_RATE: Hertz = Hertz(1_000);
+
 /// Process state information from a 2 state switch.
 /// [Debouncr](https://github.com/dbrgn/debouncr/) with a 4 sample array is used for debouncing.
+///
+/// Thresholds can either be given in number of calls to update or, with the `*_time` methods,
+/// in milliseconds relative to the rate set by [Switch::set_update_rate].
 pub struct Switch<T> {
     pin: T,
     state: Debouncer<u8, Repeat4>,
@@ -32,11 +128,19 @@ pub struct Switch<T> {
     switch_type: SwitchType,
     double_threshold: Option<u32>,
     held_threshold: Option<u32>,
+    repeat_threshold: Option<(u32, u32)>,
+    update_rate: Hertz,
+    double_time: Option<u32>,
+    held_time: Option<u32>,
+    repeat_time: Option<(u32, u32)>,
     was_pressed: bool,
     held_counter: u32,
     last_press_counter: u32,
     single_press: bool,
     double_press: bool,
+    long_press: bool,
+    long_pressed: bool,
+    repeat: bool,
 }
 
 impl<T> Switch<T>
@@ -54,16 +158,46 @@ where
             switch_type,
             double_threshold: None,
             held_threshold: None,
+            repeat_threshold: None,
+            update_rate: DEFAULT_UPDATE_RATE,
+            double_time: None,
+            held_time: None,
+            repeat_time: None,
             was_pressed: false,
             held_counter: 0,
             last_press_counter: 0,
             single_press: false,
             double_press: false,
+            long_press: false,
+            long_pressed: false,
+            repeat: false,
+        }
+    }
+
+    // At least one update, so short times don't fire on the first update at low rates
+    fn ms_to_updates(&self, ms: u32) -> u32 {
+        (((ms as u64 * self.update_rate.0 as u64) / MILLI as u64) as u32).max(1)
+    }
+
+    /// Set how often update is called, used to convert the `*_time` thresholds.
+    /// Defaults to 1 kHz, where milliseconds and calls to update are equal.
+    pub fn set_update_rate(&mut self, update_rate: Hertz) {
+        self.update_rate = update_rate;
+        // Re-apply any thresholds given as durations
+        if let Some(held_time) = self.held_time {
+            self.set_held_time(Some(held_time));
+        }
+        if let Some(double_time) = self.double_time {
+            self.set_double_time(Some(double_time));
+        }
+        if let Some((delay, interval)) = self.repeat_time {
+            self.set_repeat_time(Some((delay, interval)));
         }
     }
 
     /// Set the threshold in number of calls to update.
     pub fn set_held_thresh(&mut self, held_threshold: Option<u32>) {
+        self.held_time = None;
         self.held_threshold = if let Some(held_threshold) = held_threshold {
             Some(held_threshold)
         } else {
@@ -73,6 +207,7 @@ where
 
     /// Set the threshold in number of calls to update.
     pub fn set_double_thresh(&mut self, double_threshold: Option<u32>) {
+        self.double_time = None;
         self.double_threshold = if let Some(double_threshold) = double_threshold {
             Some(double_threshold)
         } else {
@@ -80,6 +215,32 @@ where
         };
     }
 
+    /// Set the repeat `(delay, interval)` in number of calls to update.
+    /// After the switch is held for `delay`, a repeat event occurs every `interval`.
+    pub fn set_repeat_thresh(&mut self, repeat_threshold: Option<(u32, u32)>) {
+        self.repeat_time = None;
+        self.repeat_threshold = repeat_threshold;
+    }
+
+    /// Set the held threshold in milliseconds.
+    pub fn set_held_time(&mut self, held_time: Option<u32>) {
+        self.held_threshold = held_time.map(|ms| self.ms_to_updates(ms));
+        self.held_time = held_time;
+    }
+
+    /// Set the double press threshold in milliseconds.
+    pub fn set_double_time(&mut self, double_time: Option<u32>) {
+        self.double_threshold = double_time.map(|ms| self.ms_to_updates(ms));
+        self.double_time = double_time;
+    }
+
+    /// Set the repeat `(delay, interval)` in milliseconds.
+    pub fn set_repeat_time(&mut self, repeat_time: Option<(u32, u32)>) {
+        self.repeat_threshold = repeat_time
+            .map(|(delay, interval)| (self.ms_to_updates(delay), self.ms_to_updates(interval)));
+        self.repeat_time = repeat_time;
+    }
+
     /// Read the state of the switch and update status. This should be called on a timer.
     pub fn update(&mut self) {
         let is_pressed = self.is_pressed();
@@ -119,13 +280,35 @@ where
             }
         }
 
-        // Handle held counter
-        if is_pressed {
-            self.held_counter += 1;
+        // Handle held counter, on the debounced state so bounce doesn't shift the timing
+        let is_down = self.state.is_high();
+        if is_down {
+            self.held_counter = self.held_counter.saturating_add(1);
         }
         if self.rising {
             self.held_counter = 0;
+            self.long_pressed = false;
         }
+
+        // Handle events while held, the long press is latched so a threshold changed during
+        // the press can't skip or repeat it
+        self.long_press = match self.held_threshold {
+            Some(held_threshold) => {
+                is_down && !self.long_pressed && self.held_counter >= held_threshold
+            }
+            None => false,
+        };
+        if self.long_press {
+            self.long_pressed = true;
+        }
+        self.repeat = match self.repeat_threshold {
+            Some((delay, interval)) => {
+                is_down
+                    && self.held_counter >= delay
+                    && (self.held_counter - delay) % interval.max(1) == 0
+            }
+            None => false,
+        };
     }
 
     /// If the switch state is high
@@ -156,12 +339,25 @@ where
         self.falling
     }
 
-    /// If the switch is being held
+    /// If the switch was released after being held past the held threshold.
+    /// Same as [Switch::is_long_release].
     pub fn is_held(&self) -> bool {
-        if let Some(held_threshold) = self.held_threshold {
-            return self.falling && self.held_counter >= held_threshold;
-        }
-        false
+        self.is_long_release()
+    }
+
+    /// If the switch has just been held down for the held threshold, occurs once per press.
+    pub fn is_long_press(&self) -> bool {
+        self.long_press
+    }
+
+    /// If the switch was released after being held past the held threshold.
+    pub fn is_long_release(&self) -> bool {
+        self.falling && self.long_pressed
+    }
+
+    /// If a repeat event occurred while the switch is held.
+    pub fn is_repeat(&self) -> bool {
+        self.repeat
     }
 
     /// If the switch pressed twice inside the provided threshold
@@ -170,9 +366,334 @@ where
     }
 }
 
+impl<T> EventSource for Switch<T>
+where
+    T: InputPin,
+    <T as InputPin>::Error: core::fmt::Debug,
+{
+    fn push_events<const N: usize>(&self, id: u8, producer: &mut EventProducer<'_, N>) -> bool {
+        let events = [
+            (self.is_rising(), HidEvent::Pressed(id)),
+            (self.is_long_press(), HidEvent::LongPress(id)),
+            (self.is_repeat(), HidEvent::Repeat(id)),
+            (self.is_falling(), HidEvent::Released(id)),
+            (self.is_long_release(), HidEvent::LongRelease(id)),
+            (self.is_double(), HidEvent::DoublePress(id)),
+        ];
+        let mut ok = true;
+        for (_, event) in events.iter().filter(|(active, _)| *active) {
+            ok &= push_event(producer, *event);
+        }
+        ok
+    }
+}
+
+/// Position of a 3 position switch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwitchPosition {
+    Up,
+    Center,
+    Down,
+}
+
+/// Process state information from a 3 position (ON-OFF-ON) switch wired to 2 pins.
+/// Each pin is debounced like [Switch]. If both pins read as active the switch is
+/// reported as [SwitchPosition::Center].
+pub struct Switch3<U, D> {
+    pin_up: U,
+    pin_down: D,
+    switch_type: SwitchType,
+    state_up: Debouncer<u8, Repeat4>,
+    state_down: Debouncer<u8, Repeat4>,
+    position: SwitchPosition,
+    previous: SwitchPosition,
+    changed: bool,
+}
+
+impl<U, D> Switch3<U, D>
+where
+    U: InputPin,
+    <U as InputPin>::Error: core::fmt::Debug,
+    D: InputPin,
+    <D as InputPin>::Error: core::fmt::Debug,
+{
+    /// Create a new Switch3, `pin_up` is active in the up position and `pin_down` in the down
+    /// position.
+    pub fn new(pin_up: U, pin_down: D, switch_type: SwitchType) -> Self {
+        Self {
+            pin_up,
+            pin_down,
+            switch_type,
+            state_up: debounce_4(false),
+            state_down: debounce_4(false),
+            position: SwitchPosition::Center,
+            previous: SwitchPosition::Center,
+            changed: false,
+        }
+    }
+
+    fn is_active<P>(pin: &P, switch_type: SwitchType) -> bool
+    where
+        P: InputPin,
+        <P as InputPin>::Error: core::fmt::Debug,
+    {
+        match switch_type {
+            SwitchType::PullUp => pin.is_low().unwrap(),
+            SwitchType::PullDown => pin.is_high().unwrap(),
+        }
+    }
+
+    /// Read the state of the switch and update status. This should be called on a timer.
+    pub fn update(&mut self) {
+        self.state_up
+            .update(Self::is_active(&self.pin_up, self.switch_type));
+        self.state_down
+            .update(Self::is_active(&self.pin_down, self.switch_type));
+
+        let position = match (self.state_up.is_high(), self.state_down.is_high()) {
+            (true, false) => SwitchPosition::Up,
+            (false, true) => SwitchPosition::Down,
+            _ => SwitchPosition::Center,
+        };
+
+        self.changed = position != self.position;
+        if self.changed {
+            self.previous = self.position;
+            self.position = position;
+        }
+    }
+
+    /// The current debounced position.
+    pub fn position(&self) -> SwitchPosition {
+        self.position
+    }
+
+    /// The position before the most recent change.
+    pub fn previous_position(&self) -> SwitchPosition {
+        self.previous
+    }
+
+    /// If the position changed on the last update.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Get the pins associated with this switch.
+    pub fn get_pins(&mut self) -> (&mut U, &mut D) {
+        (&mut self.pin_up, &mut self.pin_down)
+    }
+}
+
+impl<U, D> EventSource for Switch3<U, D>
+where
+    U: InputPin,
+    <U as InputPin>::Error: core::fmt::Debug,
+    D: InputPin,
+    <D as InputPin>::Error: core::fmt::Debug,
+{
+    fn push_events<const N: usize>(&self, id: u8, producer: &mut EventProducer<'_, N>) -> bool {
+        if self.changed {
+            push_event(producer, HidEvent::Position(id, self.position))
+        } else {
+            true
+        }
+    }
+}
+
+/// Quadrature rotary encoder, both pins are active low with pull-ups.
+/// Debounced by requiring a stable transition over consecutive updates.
+pub struct Encoder<A, B> {
+    pin_a: A,
+    pin_b: B,
+    state_a: u8,
+    state_b: u8,
+    increment: i32,
+}
+
+impl<A, B> Encoder<A, B>
+where
+    A: InputPin,
+    <A as InputPin>::Error: core::fmt::Debug,
+    B: InputPin,
+    <B as InputPin>::Error: core::fmt::Debug,
+{
+    /// Create a new Encoder.
+    pub fn new(pin_a: A, pin_b: B) -> Self {
+        Self {
+            pin_a,
+            pin_b,
+            state_a: 0xFF,
+            state_b: 0xFF,
+            increment: 0,
+        }
+    }
+
+    /// Read the state of the encoder and update status. This should be called on a timer,
+    /// ~1 kHz is typical.
+    pub fn update(&mut self) {
+        self.state_a = (self.state_a << 1) | self.pin_a.is_high().unwrap() as u8;
+        self.state_b = (self.state_b << 1) | self.pin_b.is_high().unwrap() as u8;
+
+        // Falling edge on one pin while the other is low determines the direction
+        self.increment = if (self.state_a & 0x03) == 0x02 && (self.state_b & 0x03) == 0x00 {
+            1
+        } else if (self.state_b & 0x03) == 0x02 && (self.state_a & 0x03) == 0x00 {
+            -1
+        } else {
+            0
+        };
+    }
+
+    /// The steps turned on the last update, positive is clockwise.
+    pub fn increment(&self) -> i32 {
+        self.increment
+    }
+
+    /// Get the pins associated with this encoder.
+    pub fn get_pins(&mut self) -> (&mut A, &mut B) {
+        (&mut self.pin_a, &mut self.pin_b)
+    }
+}
+
+impl<A, B> EventSource for Encoder<A, B>
+where
+    A: InputPin,
+    <A as InputPin>::Error: core::fmt::Debug,
+    B: InputPin,
+    <B as InputPin>::Error: core::fmt::Debug,
+{
+    fn push_events<const N: usize>(&self, id: u8, producer: &mut EventProducer<'_, N>) -> bool {
+        if self.increment != 0 {
+            push_event(producer, HidEvent::Encoder(id, self.increment))
+        } else {
+            true
+        }
+    }
+}
+
 const ANALOG_ARR_SIZE: usize = 4;
 const ANALOG_ARR_SIZE_F32: f32 = ANALOG_ARR_SIZE as f32;
 
+/// Smoothing applied to the readings of an [AnalogControl].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnalogFilter {
+    /// No smoothing, the latest reading is used.
+    None,
+    /// Average of the last 4 readings, the default.
+    Average,
+    /// One pole low pass with the given coefficient, see [AnalogFilter::one_pole].
+    OnePole(f32),
+}
+
+impl AnalogFilter {
+    /// One pole low pass with a time constant in milliseconds, when updated at `update_rate`.
+    pub fn one_pole(time_constant_ms: f32, update_rate: Hertz) -> Self {
+        let updates = time_constant_ms * update_rate.0 as f32 / MILLI as f32;
+        if updates <= 1.0 {
+            AnalogFilter::None
+        } else {
+            AnalogFilter::OnePole(1.0 - (-1.0 / updates).exp())
+        }
+    }
+}
+
+/// How an [AnalogControl] takes over from a stored value, e.g. after recalling a preset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Takeover {
+    /// The value jumps to the control position as soon as it moves.
+    Immediate,
+    /// The stored value is kept until the control crosses it.
+    Pickup,
+    /// The value moves relative to the control, scaled so that it converges with the control
+    /// position by the time the control reaches the end of its travel.
+    Scale,
+}
+
+/// Distance between the value and the control position where [Takeover::Scale] catches.
+const TAKEOVER_CATCH: f32 = 0.005;
+
+/// Calibration from a scaled reading to volts, `volts = reading * gain + offset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CvCalibration {
+    pub gain: f32,
+    pub offset: f32,
+}
+
+impl Default for CvCalibration {
+    /// Nominal -5V to +5V input, a reading of 0.0 is -5V and 1.0 is +5V.
+    fn default() -> Self {
+        Self::bipolar(5.0, false)
+    }
+}
+
+impl CvCalibration {
+    /// Nominal calibration for a -`range` to +`range` volt input.
+    /// Set `invert` for inputs where the highest reading is the lowest voltage.
+    pub fn bipolar(range: f32, invert: bool) -> Self {
+        if invert {
+            Self {
+                gain: -2.0 * range,
+                offset: range,
+            }
+        } else {
+            Self {
+                gain: 2.0 * range,
+                offset: -range,
+            }
+        }
+    }
+
+    /// Compute a calibration from two readings taken at known voltages.
+    /// Returns `None` if the readings are the same.
+    pub fn from_references(
+        reading_a: f32,
+        volts_a: f32,
+        reading_b: f32,
+        volts_b: f32,
+    ) -> Option<Self> {
+        let delta = reading_b - reading_a;
+        if delta.abs() < f32::EPSILON {
+            return None;
+        }
+        let gain = (volts_b - volts_a) / delta;
+        Some(Self {
+            gain,
+            offset: volts_a - reading_a * gain,
+        })
+    }
+
+    /// Convert a scaled reading to volts.
+    pub fn volts(&self, reading: f32) -> f32 {
+        reading * self.gain + self.offset
+    }
+
+    /// Serialize the coefficients, e.g. for storing in flash.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&self.gain.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.offset.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize coefficients created with [CvCalibration::to_bytes].
+    /// Returns `None` if they are not finite, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8; 8]) -> Option<Self> {
+        let mut gain = [0; 4];
+        let mut offset = [0; 4];
+        gain.copy_from_slice(&bytes[..4]);
+        offset.copy_from_slice(&bytes[4..]);
+        let calibration = Self {
+            gain: f32::from_le_bytes(gain),
+            offset: f32::from_le_bytes(offset),
+        };
+        if calibration.gain.is_finite() && calibration.offset.is_finite() {
+            Some(calibration)
+        } else {
+            None
+        }
+    }
+}
+
 /// Contains the state of an analog control (e.g. a potentiometer).
 pub struct AnalogControl<T> {
     state: [f32; ANALOG_ARR_SIZE],
@@ -180,6 +701,17 @@ pub struct AnalogControl<T> {
     transform: Option<TransformFn>,
     pin: T,
     index: usize,
+    filter: AnalogFilter,
+    filtered: f32,
+    deadband: f32,
+    output: f32,
+    changed: bool,
+    calibration: CvCalibration,
+    cv_range: f32,
+    reference: Option<(f32, f32)>,
+    takeover: Takeover,
+    takeover_value: Option<f32>,
+    updated: bool,
 }
 
 impl<T> AnalogControl<T> {
@@ -191,6 +723,17 @@ impl<T> AnalogControl<T> {
             transform: None,
             pin,
             index: 0,
+            filter: AnalogFilter::Average,
+            filtered: 0.0,
+            deadband: 0.0,
+            output: 0.0,
+            changed: false,
+            calibration: CvCalibration::default(),
+            cv_range: 5.0,
+            reference: None,
+            takeover: Takeover::Immediate,
+            takeover_value: None,
+            updated: false,
         }
     }
 
@@ -199,6 +742,25 @@ impl<T> AnalogControl<T> {
         self.scale = scale;
     }
 
+    /// Set the smoothing filter.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// // Smooth with a 20ms time constant, updated at 1kHz
+    /// control1.set_filter(hid::AnalogFilter::one_pole(20.0, Hertz(1_000)));
+    /// ```
+    pub fn set_filter(&mut self, filter: AnalogFilter) {
+        self.filter = filter;
+    }
+
+    /// Set the deadband, the value only changes once the filtered reading moves further than
+    /// `deadband` from the current value. Reaching either end of the range always updates.
+    /// The deadband is applied after scaling and before the transformation.
+    pub fn set_deadband(&mut self, deadband: f32) {
+        self.deadband = deadband.max(0.0);
+    }
+
     /// Provide an optional transformation function.
     ///
     /// # Example
@@ -223,24 +785,351 @@ impl<T> AnalogControl<T> {
     /// }
     /// ```
     pub fn update(&mut self, value: u32) {
-        self.state[self.index] = value as f32 / self.scale;
+        let value = value as f32 / self.scale;
+        if !self.updated {
+            //start the filter at the first reading instead of ramping up from 0
+            self.state = [value; ANALOG_ARR_SIZE];
+            self.filtered = value;
+        }
+        self.state[self.index] = value;
         self.index = (self.index + 1) % ANALOG_ARR_SIZE;
+
+        self.filtered = match self.filter {
+            AnalogFilter::None => value,
+            AnalogFilter::Average => self.state.iter().sum::<f32>() / ANALOG_ARR_SIZE_F32,
+            AnalogFilter::OnePole(coefficient) => {
+                self.filtered + (value - self.filtered) * coefficient
+            }
+        };
+
+        let at_limit = self.filtered <= 0.0 || self.filtered >= 1.0;
+        let delta = (self.filtered - self.output).abs();
+        if delta > self.deadband || (at_limit && delta > 0.0) {
+            let previous = self.output;
+            self.output = self.filtered;
+            if self.apply_takeover(previous) {
+                self.changed = true;
+            }
+        }
+        self.updated = true;
+    }
+
+    /// Apply the takeover after the position moved from `previous`.
+    /// Returns true if the value changed.
+    fn apply_takeover(&mut self, previous: f32) -> bool {
+        let stored = match self.takeover_value {
+            Some(stored) => stored,
+            None => return true,
+        };
+        let position = self.output;
+        // Before the first update there is no position it moved from
+        if !self.updated && self.takeover != Takeover::Immediate {
+            return false;
+        }
+
+        match self.takeover {
+            Takeover::Immediate => {
+                self.takeover_value = None;
+                true
+            }
+            Takeover::Pickup => {
+                // Caught when the position reaches or crosses the stored value
+                if (previous - stored) * (position - stored) <= 0.0 {
+                    self.takeover_value = None;
+                    true
+                } else {
+                    false
+                }
+            }
+            Takeover::Scale => {
+                // Move the value by the same fraction of its remaining travel as the position
+                let delta = position - previous;
+                let value = if delta > 0.0 && 1.0 - previous > f32::EPSILON {
+                    stored + delta * (1.0 - stored) / (1.0 - previous)
+                } else if delta < 0.0 && previous > f32::EPSILON {
+                    stored + delta * stored / previous
+                } else {
+                    position
+                };
+
+                self.takeover_value = if (value - position).abs() <= TAKEOVER_CATCH {
+                    None
+                } else {
+                    Some(value.clamp(0.0, 1.0))
+                };
+                true
+            }
+        }
+    }
+
+    /// Set how the control takes over from a stored value.
+    pub fn set_takeover(&mut self, takeover: Takeover) {
+        self.takeover = takeover;
+        if takeover == Takeover::Immediate {
+            self.takeover_value = None;
+        }
+    }
+
+    /// Set a stored value, e.g. from a preset, in scaled units before the transformation.
+    /// Until the control takes over [AnalogControl::get_value] is based on this value.
+    /// Has no effect with [Takeover::Immediate].
+    pub fn set_stored_value(&mut self, value: f32) {
+        if self.takeover != Takeover::Immediate {
+            self.takeover_value = Some(value);
+            self.changed = true;
+        }
+    }
+
+    /// If the control has taken over from the stored value.
+    pub fn is_caught(&self) -> bool {
+        self.takeover_value.is_none()
+    }
+
+    /// Get the physical position of the control in scaled units, ignoring any stored value.
+    pub fn get_position(&self) -> f32 {
+        self.output
     }
 
     /// Get the value of the control with any applied scaling and/or transformation.
     pub fn get_value(&self) -> f32 {
-        let mut value = self.state.iter().sum();
-        value /= ANALOG_ARR_SIZE_F32;
+        let mut value = self.takeover_value.unwrap_or(self.output);
         if let Some(tfn) = self.transform {
             value = tfn(value);
         }
         value
     }
 
+    /// If the value changed since it was last read with [AnalogControl::read_changed].
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Get the value if it changed since the last call, and clear the changed flag.
+    pub fn read_changed(&mut self) -> Option<f32> {
+        if self.changed {
+            self.changed = false;
+            Some(self.get_value())
+        } else {
+            None
+        }
+    }
+
     /// Get the pin associated with this control.
     pub fn get_pin(&mut self) -> &mut T {
         &mut self.pin
     }
+
+    /// Set the calibration used for CV input.
+    pub fn set_cv_calibration(&mut self, calibration: CvCalibration) {
+        self.calibration = calibration;
+    }
+
+    /// Get the calibration used for CV input.
+    pub fn cv_calibration(&self) -> CvCalibration {
+        self.calibration
+    }
+
+    /// Set the voltage that maps to +/-1.0 for [AnalogControl::get_bipolar], defaults to 5V.
+    pub fn set_cv_range(&mut self, range: f32) {
+        self.cv_range = range;
+    }
+
+    /// Get the CV input in volts, the transformation is not applied.
+    pub fn get_volts(&self) -> f32 {
+        self.calibration.volts(self.output)
+    }
+
+    /// Get the CV input mapped from -range..range volts to -1.0..1.0.
+    pub fn get_bipolar(&self) -> f32 {
+        (self.get_volts() / self.cv_range).clamp(-1.0, 1.0)
+    }
+
+    /// Get the CV input as a note number at 1V per octave, 0V is `base_note`.
+    pub fn get_voct_note(&self, base_note: f32) -> f32 {
+        base_note + self.get_volts() * 12.0
+    }
+
+    /// Get the CV input as a frequency at 1V per octave, 0V is `base_freq`.
+    pub fn get_voct_freq(&self, base_freq: f32) -> f32 {
+        base_freq * (self.get_volts() * core::f32::consts::LN_2).exp()
+    }
+
+    /// Calibrate by capturing the current reading as the reference for `volts`.
+    /// Apply a known voltage, let the reading settle, then capture. Once two references are
+    /// captured the calibration is computed, stored and returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// // Patch 1V into the input
+    /// cv1.capture_reference(1.0);
+    /// // Patch 3V into the input
+    /// if let Some(calibration) = cv1.capture_reference(3.0) {
+    ///     // store calibration.to_bytes() in flash
+    /// }
+    /// ```
+    pub fn capture_reference(&mut self, volts: f32) -> Option<CvCalibration> {
+        match self.reference.take() {
+            None => {
+                self.reference = Some((self.output, volts));
+                None
+            }
+            Some((reading, reference_volts)) => {
+                let calibration =
+                    CvCalibration::from_references(reading, reference_volts, self.output, volts)?;
+                self.calibration = calibration;
+                Some(calibration)
+            }
+        }
+    }
+
+    /// Discard a partially captured calibration.
+    pub fn clear_reference(&mut self) {
+        self.reference = None;
+    }
+}
+
+/// Mapping curve from a normalized control value to a [Parameter]'s range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    /// Straight line from min to max.
+    Linear,
+    /// Squared, more resolution at the low end.
+    Quadratic,
+    /// Exponential, equal ratios per distance e.g. frequencies. Min must be greater than 0.
+    Exponential,
+    /// Cubed, even more resolution at the low end.
+    Cube,
+}
+
+/// Maps an [AnalogControl] to a range with a [Curve], optional custom shaping and
+/// quantization.
+///
+/// # Example
+///
+/// ```rust
+/// // Cutoff frequency from 20Hz to 20kHz
+/// let mut cutoff = hid::Parameter::new(control1, 20.0, 20_000.0, hid::Curve::Exponential);
+/// // Select one of 4 waveforms, with a custom closure
+/// let offset = 0.1;
+/// let mut wave = hid::Parameter::new(control2, 0.0, 3.0, hid::Curve::Linear)
+///     .with_shape(move |x| x + offset);
+/// wave.set_steps(Some(4));
+/// ```
+pub struct Parameter<T, F = fn(f32) -> f32> {
+    control: AnalogControl<T>,
+    min: f32,
+    max: f32,
+    curve: Curve,
+    shape: Option<F>,
+    steps: Option<u32>,
+}
+
+impl<T> Parameter<T> {
+    /// Create a new Parameter from `min` to `max` with the given curve.
+    pub fn new(control: AnalogControl<T>, min: f32, max: f32, curve: Curve) -> Self {
+        Self {
+            control,
+            min,
+            max,
+            curve,
+            shape: None,
+            steps: None,
+        }
+    }
+}
+
+impl<T, F> Parameter<T, F>
+where
+    F: Fn(f32) -> f32,
+{
+    /// Shape the normalized value (0.0 to 1.0) with a function or closure before the curve is
+    /// applied.
+    pub fn with_shape<G>(self, shape: G) -> Parameter<T, G>
+    where
+        G: Fn(f32) -> f32,
+    {
+        Parameter {
+            control: self.control,
+            min: self.min,
+            max: self.max,
+            curve: self.curve,
+            shape: Some(shape),
+            steps: self.steps,
+        }
+    }
+
+    /// Set the output range.
+    pub fn set_range(&mut self, min: f32, max: f32) {
+        self.min = min;
+        self.max = max;
+    }
+
+    /// Set the curve.
+    pub fn set_curve(&mut self, curve: Curve) {
+        self.curve = curve;
+    }
+
+    /// Quantize the output to `steps` evenly spaced values from min to max, `None` to disable.
+    pub fn set_steps(&mut self, steps: Option<u32>) {
+        self.steps = steps.filter(|steps| *steps > 0);
+    }
+
+    /// Update the underlying control, see [AnalogControl::update].
+    pub fn update(&mut self, value: u32) {
+        self.control.update(value);
+    }
+
+    /// Get the value mapped to the range.
+    pub fn get_value(&self) -> f32 {
+        let mut x = self.control.get_value().clamp(0.0, 1.0);
+        if let Some(shape) = &self.shape {
+            x = shape(x).clamp(0.0, 1.0);
+        }
+
+        let range = self.max - self.min;
+        let value = match self.curve {
+            Curve::Linear => self.min + range * x,
+            Curve::Quadratic => self.min + range * x * x,
+            Curve::Cube => self.min + range * x * x * x,
+            Curve::Exponential => {
+                let lmin = self.min.max(f32::MIN_POSITIVE).ln();
+                let lmax = self.max.max(f32::MIN_POSITIVE).ln();
+                (lmin + (lmax - lmin) * x).exp()
+            }
+        };
+
+        match self.steps {
+            Some(steps) if steps > 1 && range != 0.0 => {
+                let step = range / (steps - 1) as f32;
+                self.min + ((value - self.min) / step).round() * step
+            }
+            Some(_) => self.min,
+            None => value,
+        }
+    }
+
+    /// Get the step index from 0 to steps - 1, 0 if quantization is disabled.
+    pub fn get_index(&self) -> u32 {
+        let range = self.max - self.min;
+        match self.steps {
+            Some(steps) if steps > 1 && range != 0.0 => {
+                let position = (self.get_value() - self.min) / range;
+                ((position * (steps - 1) as f32).round() as u32).min(steps - 1)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Get the underlying control.
+    pub fn control(&mut self) -> &mut AnalogControl<T> {
+        &mut self.control
+    }
+
+    /// Release the underlying control.
+    pub fn free(self) -> AnalogControl<T> {
+        self.control
+    }
 }
 
 /// Basic LED implementation with a PWM like functional. Does not implement PWM via hardware.
@@ -252,10 +1141,7 @@ pub struct Led<T> {
     resolution: u32,
     brightness: f32,
     pwm: f32,
-    blink_on: Option<u32>,
-    blink_off: Option<u32>,
-    blink_counter: u32,
-    blink_status: BlinkStatus,
+    blink: Blink,
 }
 
 impl<T> Led<T>
@@ -270,10 +1156,7 @@ where
             resolution,
             brightness: 0.0,
             pwm: 0.0,
-            blink_on: None,
-            blink_off: None,
-            blink_counter: 0,
-            blink_status: BlinkStatus::Disabled,
+            blink: Blink::new(),
         }
     }
 
@@ -297,42 +1180,18 @@ where
     /// Enable blink functionality.
     /// Times are in resolution multiplied by blink_on/blink_off.
     pub fn set_blink(&mut self, blink_on: f32, blink_off: f32) {
-        self.blink_on = Some((blink_on * self.resolution as f32) as u32);
-        self.blink_off = Some((blink_off * self.resolution as f32) as u32);
+        self.blink.set(blink_on, blink_off, self.resolution);
     }
 
     /// Disable blink.
     pub fn clear_blink(&mut self) {
-        self.blink_on = None;
-        self.blink_off = None;
-        self.blink_status = BlinkStatus::Disabled;
+        self.blink.clear();
     }
 
     /// Update LED status. This should be called on a timer.
     pub fn update(&mut self) {
         // Calculate blink status
-        if let (Some(blink_on), Some(blink_off)) = (self.blink_on, self.blink_off) {
-            self.blink_counter += 1;
-            self.blink_status = match self.blink_status {
-                BlinkStatus::On => {
-                    if self.blink_counter > blink_on {
-                        self.blink_counter = 0;
-                        BlinkStatus::Off
-                    } else {
-                        BlinkStatus::On
-                    }
-                }
-                BlinkStatus::Off => {
-                    if self.blink_counter > blink_off {
-                        self.blink_counter = 0;
-                        BlinkStatus::On
-                    } else {
-                        BlinkStatus::Off
-                    }
-                }
-                BlinkStatus::Disabled => BlinkStatus::On,
-            };
-        };
+        self.blink.update();
 
         self.pwm += 1.0 / self.resolution as f32;
         if self.pwm > 1.0 {
@@ -344,7 +1203,7 @@ where
         } else {
             false
         };
-        match self.blink_status {
+        match self.blink.status {
             BlinkStatus::On => self.pin.set_high().ok().unwrap(),
             BlinkStatus::Off => self.pin.set_low().ok().unwrap(),
             BlinkStatus::Disabled => {
@@ -357,3 +1216,281 @@ where
         };
     }
 }
+
+/// Blink state shared by the LED types.
+struct Blink {
+    on: Option<u32>,
+    off: Option<u32>,
+    counter: u32,
+    status: BlinkStatus,
+}
+
+impl Blink {
+    fn new() -> Self {
+        Self {
+            on: None,
+            off: None,
+            counter: 0,
+            status: BlinkStatus::Disabled,
+        }
+    }
+
+    fn set(&mut self, on: f32, off: f32, resolution: u32) {
+        self.on = Some((on * resolution as f32) as u32);
+        self.off = Some((off * resolution as f32) as u32);
+    }
+
+    fn clear(&mut self) {
+        self.on = None;
+        self.off = None;
+        self.status = BlinkStatus::Disabled;
+    }
+
+    fn update(&mut self) -> BlinkStatus {
+        if let (Some(on), Some(off)) = (self.on, self.off) {
+            self.counter += 1;
+            self.status = match self.status {
+                BlinkStatus::On => {
+                    if self.counter > on {
+                        self.counter = 0;
+                        BlinkStatus::Off
+                    } else {
+                        BlinkStatus::On
+                    }
+                }
+                BlinkStatus::Off => {
+                    if self.counter > off {
+                        self.counter = 0;
+                        BlinkStatus::On
+                    } else {
+                        BlinkStatus::Off
+                    }
+                }
+                BlinkStatus::Disabled => BlinkStatus::On,
+            };
+        };
+        self.status
+    }
+}
+
+/// An RGB color, each component from 0.0 to 1.0.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+impl Color {
+    pub const OFF: Color = Color::new(0.0, 0.0, 0.0);
+    pub const WHITE: Color = Color::new(1.0, 1.0, 1.0);
+    pub const RED: Color = Color::new(1.0, 0.0, 0.0);
+    pub const GREEN: Color = Color::new(0.0, 1.0, 0.0);
+    pub const BLUE: Color = Color::new(0.0, 0.0, 1.0);
+    pub const YELLOW: Color = Color::new(1.0, 1.0, 0.0);
+    pub const CYAN: Color = Color::new(0.0, 1.0, 1.0);
+    pub const MAGENTA: Color = Color::new(1.0, 0.0, 1.0);
+
+    /// Create a new color from red, green and blue components.
+    pub const fn new(red: f32, green: f32, blue: f32) -> Self {
+        Self { red, green, blue }
+    }
+
+    /// Create a color from hue, saturation and value, all from 0.0 to 1.0.
+    /// Hue wraps around, so 0.0 and 1.0 are both red.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let saturation = saturation.clamp(0.0, 1.0);
+        let value = value.clamp(0.0, 1.0);
+        let hue = (hue - hue.floor()) * 6.0;
+        let sector = hue.floor();
+        let fraction = hue - sector;
+
+        let p = value * (1.0 - saturation);
+        let q = value * (1.0 - saturation * fraction);
+        let t = value * (1.0 - saturation * (1.0 - fraction));
+
+        match sector as u32 {
+            0 => Self::new(value, t, p),
+            1 => Self::new(q, value, p),
+            2 => Self::new(p, value, t),
+            3 => Self::new(p, q, value),
+            4 => Self::new(t, p, value),
+            _ => Self::new(value, p, q),
+        }
+    }
+
+    /// Linearly blend towards `other`, `amount` 0.0 is `self` and 1.0 is `other`.
+    pub fn blend(&self, other: Color, amount: f32) -> Self {
+        let amount = amount.clamp(0.0, 1.0);
+        Self::new(
+            self.red + (other.red - self.red) * amount,
+            self.green + (other.green - self.green) * amount,
+            self.blue + (other.blue - self.blue) * amount,
+        )
+    }
+
+    /// Scale all components by `brightness`.
+    pub fn scale(&self, brightness: f32) -> Self {
+        Self::new(
+            self.red * brightness,
+            self.green * brightness,
+            self.blue * brightness,
+        )
+    }
+}
+
+/// A single brightness channel used by [RgbLed].
+pub trait LedChannel {
+    /// Set the output level from 0.0 (off) to 1.0 (fully on).
+    fn set_level(&mut self, level: f32);
+
+    /// Called from [RgbLed::update], software PWM channels toggle their pin here.
+    fn update(&mut self) {}
+}
+
+/// Software PWM via [Led], the LED's own blink and brightness curve are bypassed.
+impl<T> LedChannel for Led<T>
+where
+    T: OutputPin,
+{
+    fn set_level(&mut self, level: f32) {
+        self.brightness = level.clamp(0.0, 1.0);
+    }
+
+    fn update(&mut self) {
+        Led::update(self);
+    }
+}
+
+/// LED channel driven by a hardware PWM output, e.g. a timer channel.
+pub struct PwmChannel<P> {
+    pin: P,
+    invert: bool,
+}
+
+impl<P> PwmChannel<P>
+where
+    P: PwmPin<Duty = u16>,
+{
+    /// Create a new PWM channel and enable the output.
+    /// Set `invert` for common anode LEDs.
+    pub fn new(mut pin: P, invert: bool) -> Self {
+        pin.enable();
+        Self { pin, invert }
+    }
+
+    /// Release the PWM pin.
+    pub fn free(self) -> P {
+        self.pin
+    }
+}
+
+impl<P> LedChannel for PwmChannel<P>
+where
+    P: PwmPin<Duty = u16>,
+{
+    fn set_level(&mut self, level: f32) {
+        let level = level.clamp(0.0, 1.0);
+        let level = if self.invert { 1.0 - level } else { level };
+        let duty = (self.pin.get_max_duty() as f32 * level) as u16;
+        self.pin.set_duty(duty);
+    }
+}
+
+/// RGB LED made up of three [LedChannel]s with color, gamma correction and blink.
+pub struct RgbLed<R, G, B> {
+    red: R,
+    green: G,
+    blue: B,
+    /// resolution is the number of update calls per blink time unit
+    resolution: u32,
+    color: Color,
+    brightness: f32,
+    gamma: bool,
+    blink: Blink,
+}
+
+impl<R, G, B> RgbLed<R, G, B>
+where
+    R: LedChannel,
+    G: LedChannel,
+    B: LedChannel,
+{
+    /// Create a new RGB LED, gamma correction is enabled by default.
+    pub fn new(red: R, green: G, blue: B, resolution: u32) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            resolution,
+            color: Color::OFF,
+            brightness: 1.0,
+            gamma: true,
+            blink: Blink::new(),
+        }
+    }
+
+    /// Set the color.
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+
+    /// Set the color from red, green and blue components from 0.0 to 1.0.
+    pub fn set_rgb(&mut self, red: f32, green: f32, blue: f32) {
+        self.color = Color::new(red, green, blue);
+    }
+
+    /// Set the color from hue, saturation and value from 0.0 to 1.0.
+    pub fn set_hsv(&mut self, hue: f32, saturation: f32, value: f32) {
+        self.color = Color::from_hsv(hue, saturation, value);
+    }
+
+    /// Get the current color.
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    /// Set the overall brightness from 0.0 to 1.0, applied on top of the color.
+    pub fn set_brightness(&mut self, value: f32) {
+        self.brightness = value.clamp(0.0, 1.0);
+    }
+
+    /// Enable or disable gamma correction.
+    pub fn set_gamma(&mut self, enable: bool) {
+        self.gamma = enable;
+    }
+
+    /// Enable blink functionality.
+    /// Times are in resolution multiplied by blink_on/blink_off.
+    pub fn set_blink(&mut self, blink_on: f32, blink_off: f32) {
+        self.blink.set(blink_on, blink_off, self.resolution);
+    }
+
+    /// Disable blink.
+    pub fn clear_blink(&mut self) {
+        self.blink.clear();
+    }
+
+    /// Update LED status. This should be called on a timer.
+    pub fn update(&mut self) {
+        let color = match self.blink.update() {
+            BlinkStatus::Off => Color::OFF,
+            _ => self.color.scale(self.brightness),
+        };
+        let use_gamma = self.gamma;
+        let correct = |v: f32| if use_gamma { gamma(v) } else { v };
+
+        self.red.set_level(correct(color.red));
+        self.green.set_level(correct(color.green));
+        self.blue.set_level(correct(color.blue));
+
+        self.red.update();
+        self.green.update();
+        self.blue.update();
+    }
+
+    /// Get mutable references to the channels.
+    pub fn channels(&mut self) -> (&mut R, &mut G, &mut B) {
+        (&mut self.red, &mut self.green, &mut self.blue)
+    }
+}
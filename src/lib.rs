@@ -27,15 +27,26 @@ pub type FrameTimer = stm32h7xx_hal::timer::Timer<stm32h7xx_hal::stm32::TIM2>;
 pub use stm32h7xx_hal as hal;
 
 pub mod audio;
+pub mod dac;
+pub mod dac7554;
+pub mod display;
 pub mod flash;
+pub mod gate;
 pub mod gpio;
 pub mod hid;
 pub mod logger;
+pub mod midi;
+pub mod midi_clock;
 pub mod mpu;
+pub mod notes;
+pub mod pca9685;
 pub mod prelude;
 pub mod sdmmc;
 pub mod sdram;
 pub mod system;
+pub mod ui;
+#[cfg(feature = "usb-midi")]
+pub mod usb_midi;
 
 pub mod field;
 
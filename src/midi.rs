@@ -0,0 +1,593 @@
+//! MIDI messages and a parser for MIDI byte streams, e.g. serial MIDI on the Field or any
+//! Daisy UART, with an interrupt driven transport in [MidiUart].
+//!
+//! # Example
+//!
+//! ```rust
+//! // USART1 on Daisy pins 13 (TX) and 14 (RX)
+//! let mut serial = device
+//!     .USART1
+//!     .serial(
+//!         (daisy13.into_alternate_af7(), daisy14.into_alternate_af7()),
+//!         midi::serial_config(),
+//!         ccdr.peripheral.USART1,
+//!         &ccdr.clocks,
+//!     )
+//!     .unwrap();
+//! let mut parser = MidiParser::new();
+//!
+//! loop {
+//!     match parser.read(&mut serial) {
+//!         Ok(Some(MidiEvent::NoteOn { note, velocity, .. })) => synth.note_on(note, velocity),
+//!         Ok(Some(MidiEvent::SysEx)) => info!("SysEx {:?}", parser.sysex()),
+//!         _ => (),
+//!     }
+//! }
+//! ```
+use core::marker::PhantomData;
+use heapless::{
+    spsc::{Consumer, Producer, Queue},
+    Vec,
+};
+use stm32h7xx_hal::{self as hal, hal::serial, nb, prelude::*, serial::config::Config};
+
+/// Serial MIDI baud rate.
+pub const MIDI_BAUD: u32 = 31_250;
+
+/// Longest SysEx message kept, excluding the start and end bytes.
+pub const SYSEX_LEN: usize = 128;
+
+/// Pitch bend value with the wheel centered.
+pub const PITCH_BEND_CENTER: u16 = 0x2000;
+
+/// Serial configuration for MIDI, 31250 baud with 8 data bits, no parity and 1 stop bit.
+pub fn serial_config() -> Config {
+    Config::default().baudrate(MIDI_BAUD.bps()).parity_none()
+}
+
+/// A MIDI message. Channels count from 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MidiEvent {
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    /// A note on with velocity 0 is parsed as a [MidiEvent::NoteOff].
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    PolyPressure {
+        channel: u8,
+        note: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        channel: u8,
+        control: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    ChannelPressure {
+        channel: u8,
+        pressure: u8,
+    },
+    /// 14-bit value, [PITCH_BEND_CENTER] is centered.
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+    /// A complete SysEx message, its data is in [MidiParser::sysex].
+    SysEx,
+    TimeCodeQuarterFrame(u8),
+    /// Position in sixteenth notes.
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+impl MidiEvent {
+    /// The channel of a channel message.
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiEvent::NoteOff { channel, .. }
+            | MidiEvent::NoteOn { channel, .. }
+            | MidiEvent::PolyPressure { channel, .. }
+            | MidiEvent::ControlChange { channel, .. }
+            | MidiEvent::ProgramChange { channel, .. }
+            | MidiEvent::ChannelPressure { channel, .. }
+            | MidiEvent::PitchBend { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+
+    /// Returns true for realtime messages, which can be sent in the middle of other messages.
+    pub fn is_realtime(&self) -> bool {
+        matches!(
+            self,
+            MidiEvent::Clock
+                | MidiEvent::Start
+                | MidiEvent::Continue
+                | MidiEvent::Stop
+                | MidiEvent::ActiveSensing
+                | MidiEvent::Reset
+        )
+    }
+
+    /// The message as bytes, without running status. Empty for [MidiEvent::SysEx].
+    pub fn to_bytes(&self) -> Vec<u8, 3> {
+        let channel_status = |status: u8, channel: u8| status | (channel & 0x0F);
+        //data bytes are 7-bit
+        let message = |bytes: &[u8]| -> Vec<u8, 3> {
+            bytes
+                .iter()
+                .enumerate()
+                .map(|(i, b)| if i == 0 { *b } else { *b & 0x7F })
+                .collect()
+        };
+        match *self {
+            MidiEvent::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => message(&[channel_status(0x80, channel), note, velocity]),
+            MidiEvent::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => message(&[channel_status(0x90, channel), note, velocity]),
+            MidiEvent::PolyPressure {
+                channel,
+                note,
+                pressure,
+            } => message(&[channel_status(0xA0, channel), note, pressure]),
+            MidiEvent::ControlChange {
+                channel,
+                control,
+                value,
+            } => message(&[channel_status(0xB0, channel), control, value]),
+            MidiEvent::ProgramChange { channel, program } => {
+                message(&[channel_status(0xC0, channel), program])
+            }
+            MidiEvent::ChannelPressure { channel, pressure } => {
+                message(&[channel_status(0xD0, channel), pressure])
+            }
+            MidiEvent::PitchBend { channel, value } => message(&[
+                channel_status(0xE0, channel),
+                (value & 0x7F) as u8,
+                ((value >> 7) & 0x7F) as u8,
+            ]),
+            MidiEvent::SysEx => message(&[]),
+            MidiEvent::TimeCodeQuarterFrame(value) => message(&[0xF1, value]),
+            MidiEvent::SongPosition(position) => message(&[
+                0xF2,
+                (position & 0x7F) as u8,
+                ((position >> 7) & 0x7F) as u8,
+            ]),
+            MidiEvent::SongSelect(song) => message(&[0xF3, song]),
+            MidiEvent::TuneRequest => message(&[0xF6]),
+            MidiEvent::Clock => message(&[0xF8]),
+            MidiEvent::Start => message(&[0xFA]),
+            MidiEvent::Continue => message(&[0xFB]),
+            MidiEvent::Stop => message(&[0xFC]),
+            MidiEvent::ActiveSensing => message(&[0xFE]),
+            MidiEvent::Reset => message(&[0xFF]),
+        }
+    }
+}
+
+// Number of data bytes after a status byte
+fn data_len(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        0x80..=0xE0 => 2,
+        _ => match status {
+            0xF1 | 0xF3 => 1,
+            0xF2 => 2,
+            _ => 0,
+        },
+    }
+}
+
+/// Parses a MIDI byte stream into [MidiEvent]s.
+///
+/// Handles running status, realtime messages between the bytes of other messages and SysEx.
+/// A SysEx message interrupted by any status other than its end is dropped.
+pub struct MidiParser {
+    status: Option<u8>,
+    data: [u8; 2],
+    len: usize,
+    in_sysex: bool,
+    sysex: Vec<u8, SYSEX_LEN>,
+    sysex_truncated: bool,
+}
+
+impl Default for MidiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiParser {
+    /// Create a new parser.
+    pub fn new() -> Self {
+        Self {
+            status: None,
+            data: [0; 2],
+            len: 0,
+            in_sysex: false,
+            sysex: Vec::new(),
+            sysex_truncated: false,
+        }
+    }
+
+    /// The data of the last SysEx message, excluding the start and end bytes.
+    pub fn sysex(&self) -> &[u8] {
+        &self.sysex
+    }
+
+    /// Returns true if the last SysEx message was longer than [SYSEX_LEN] and was cut short.
+    pub fn sysex_truncated(&self) -> bool {
+        self.sysex_truncated
+    }
+
+    /// Forget any partial message and the running status.
+    pub fn reset(&mut self) {
+        self.status = None;
+        self.len = 0;
+        self.in_sysex = false;
+    }
+
+    /// Parse a byte, returning an event when a message is complete.
+    pub fn parse(&mut self, byte: u8) -> Option<MidiEvent> {
+        match byte {
+            0xF8..=0xFF => self.parse_realtime(byte),
+            0x80..=0xFF => self.parse_status(byte),
+            _ => self.parse_data(byte),
+        }
+    }
+
+    /// Parse all the bytes, calling `handler` with each event.
+    pub fn parse_slice<F: FnMut(MidiEvent)>(&mut self, bytes: &[u8], mut handler: F) {
+        for byte in bytes {
+            if let Some(event) = self.parse(*byte) {
+                handler(event);
+            }
+        }
+    }
+
+    /// Read a byte from a serial port and parse it.
+    pub fn read<S: serial::Read<u8>>(
+        &mut self,
+        serial: &mut S,
+    ) -> nb::Result<Option<MidiEvent>, S::Error> {
+        let byte = serial.read()?;
+        Ok(self.parse(byte))
+    }
+
+    fn parse_realtime(&mut self, byte: u8) -> Option<MidiEvent> {
+        match byte {
+            0xF8 => Some(MidiEvent::Clock),
+            0xFA => Some(MidiEvent::Start),
+            0xFB => Some(MidiEvent::Continue),
+            0xFC => Some(MidiEvent::Stop),
+            0xFE => Some(MidiEvent::ActiveSensing),
+            0xFF => {
+                self.reset();
+                Some(MidiEvent::Reset)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_status(&mut self, byte: u8) -> Option<MidiEvent> {
+        let sysex_ended = core::mem::replace(&mut self.in_sysex, false);
+        self.len = 0;
+        match byte {
+            0xF0 => {
+                self.status = None;
+                self.in_sysex = true;
+                self.sysex.clear();
+                self.sysex_truncated = false;
+                None
+            }
+            0xF7 => {
+                self.status = None;
+                if sysex_ended {
+                    Some(MidiEvent::SysEx)
+                } else {
+                    None
+                }
+            }
+            0xF6 => {
+                self.status = None;
+                Some(MidiEvent::TuneRequest)
+            }
+            0xF1..=0xF3 => {
+                self.status = Some(byte);
+                None
+            }
+            //undefined
+            0xF4 | 0xF5 => {
+                self.status = None;
+                None
+            }
+            _ => {
+                self.status = Some(byte);
+                None
+            }
+        }
+    }
+
+    fn parse_data(&mut self, byte: u8) -> Option<MidiEvent> {
+        if self.in_sysex {
+            if self.sysex.push(byte).is_err() {
+                self.sysex_truncated = true;
+            }
+            return None;
+        }
+        let status = self.status?;
+        self.data[self.len] = byte;
+        self.len += 1;
+        if self.len < data_len(status) {
+            return None;
+        }
+        self.len = 0;
+
+        let channel = status & 0x0F;
+        let [data0, data1] = self.data;
+        let event = match status & 0xF0 {
+            0x80 => MidiEvent::NoteOff {
+                channel,
+                note: data0,
+                velocity: data1,
+            },
+            0x90 if data1 == 0 => MidiEvent::NoteOff {
+                channel,
+                note: data0,
+                velocity: 0,
+            },
+            0x90 => MidiEvent::NoteOn {
+                channel,
+                note: data0,
+                velocity: data1,
+            },
+            0xA0 => MidiEvent::PolyPressure {
+                channel,
+                note: data0,
+                pressure: data1,
+            },
+            0xB0 => MidiEvent::ControlChange {
+                channel,
+                control: data0,
+                value: data1,
+            },
+            0xC0 => MidiEvent::ProgramChange {
+                channel,
+                program: data0,
+            },
+            0xD0 => MidiEvent::ChannelPressure {
+                channel,
+                pressure: data0,
+            },
+            0xE0 => MidiEvent::PitchBend {
+                channel,
+                value: data0 as u16 | (data1 as u16) << 7,
+            },
+            _ => {
+                //system common messages have no running status
+                self.status = None;
+                match status {
+                    0xF1 => MidiEvent::TimeCodeQuarterFrame(data0),
+                    0xF2 => MidiEvent::SongPosition(data0 as u16 | (data1 as u16) << 7),
+                    _ => MidiEvent::SongSelect(data0),
+                }
+            }
+        };
+        Some(event)
+    }
+}
+
+/// Byte queue between a [MidiUart] and a [MidiInput] or [MidiOutput].
+pub type MidiQueue<const N: usize> = Queue<u8, N>;
+
+/// A UART that can carry MIDI with interrupts, implemented for the HAL's serial ports.
+pub trait MidiSerial: serial::Read<u8> + serial::Write<u8> {
+    /// Enable the receive interrupt.
+    fn listen_rx(&mut self);
+
+    /// Enable or disable the transmit interrupt. Safe to call from other tasks.
+    fn listen_tx(enable: bool);
+}
+
+macro_rules! midi_serial {
+    ($($USART:ident),*) => {
+        $(
+            impl MidiSerial for hal::serial::Serial<hal::stm32::$USART> {
+                fn listen_rx(&mut self) {
+                    self.listen(hal::serial::Event::Rxne);
+                }
+
+                fn listen_tx(enable: bool) {
+                    let regs = unsafe { &*hal::stm32::$USART::ptr() };
+                    cortex_m::interrupt::free(|_| regs.cr1.modify(|_, w| w.txeie().bit(enable)));
+                }
+            }
+        )*
+    };
+}
+
+midi_serial!(USART1, USART2, USART3, UART4, UART5, USART6, UART7, UART8);
+
+/// Interrupt driven serial MIDI transport, moving bytes between the UART and queues.
+/// Call [MidiUart::handle_interrupt] from the UART's interrupt.
+///
+/// # Example
+///
+/// ```rust
+/// // In init, with `rx_queue: MidiQueue<256>` and `tx_queue: MidiQueue<256>` as local resources
+/// let (rx_producer, rx_consumer) = ctx.local.rx_queue.split();
+/// let (tx_producer, tx_consumer) = ctx.local.tx_queue.split();
+/// let uart = MidiUart::new(field.split_serial_midi(), rx_producer, tx_consumer);
+/// let input = MidiInput::new(rx_consumer);
+/// let output: MidiOutput<FieldSerialMIDI, 256> = MidiOutput::new(tx_producer);
+///
+/// // In a task bound to USART1
+/// ctx.local.uart.handle_interrupt();
+///
+/// // In another task
+/// while let Some(event) = ctx.local.input.read() {
+///     ctx.local.output.send(event);
+/// }
+/// ```
+pub struct MidiUart<'a, S, const RX: usize, const TX: usize> {
+    serial: S,
+    rx: Producer<'a, u8, RX>,
+    tx: Consumer<'a, u8, TX>,
+    dropped: u32,
+}
+
+impl<'a, S: MidiSerial, const RX: usize, const TX: usize> MidiUart<'a, S, RX, TX> {
+    /// Create a transport and enable the receive interrupt.
+    pub fn new(mut serial: S, rx: Producer<'a, u8, RX>, tx: Consumer<'a, u8, TX>) -> Self {
+        serial.listen_rx();
+        Self {
+            serial,
+            rx,
+            tx,
+            dropped: 0,
+        }
+    }
+
+    /// Move received bytes to the input queue and queued bytes to the UART.
+    pub fn handle_interrupt(&mut self) {
+        loop {
+            match self.serial.read() {
+                Ok(byte) => {
+                    if self.rx.enqueue(byte).is_err() {
+                        self.dropped = self.dropped.wrapping_add(1);
+                    }
+                }
+                //overrun, framing or noise
+                Err(nb::Error::Other(_)) => self.dropped = self.dropped.wrapping_add(1),
+                Err(nb::Error::WouldBlock) => break,
+            }
+        }
+
+        while let Some(byte) = self.tx.peek() {
+            match self.serial.write(*byte) {
+                Ok(()) => {
+                    self.tx.dequeue();
+                }
+                Err(_) => break,
+            }
+        }
+        S::listen_tx(self.tx.ready());
+    }
+
+    /// Number of received bytes lost, because the input queue was full or the UART overran.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Release the UART.
+    pub fn free(self) -> S {
+        S::listen_tx(false);
+        self.serial
+    }
+}
+
+/// Parses the bytes received by a [MidiUart].
+pub struct MidiInput<'a, const N: usize> {
+    rx: Consumer<'a, u8, N>,
+    parser: MidiParser,
+}
+
+impl<'a, const N: usize> MidiInput<'a, N> {
+    /// Create an input from the consumer end of the receive queue.
+    pub fn new(rx: Consumer<'a, u8, N>) -> Self {
+        Self {
+            rx,
+            parser: MidiParser::new(),
+        }
+    }
+
+    /// Parse received bytes until there is an event.
+    pub fn read(&mut self) -> Option<MidiEvent> {
+        while let Some(byte) = self.rx.dequeue() {
+            if let Some(event) = self.parser.parse(byte) {
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    /// Get the parser, e.g. for [MidiParser::sysex].
+    pub fn parser(&self) -> &MidiParser {
+        &self.parser
+    }
+}
+
+/// Queues messages for a [MidiUart] to send, without blocking.
+pub struct MidiOutput<'a, S, const N: usize> {
+    tx: Producer<'a, u8, N>,
+    _serial: PhantomData<S>,
+}
+
+impl<'a, S: MidiSerial, const N: usize> MidiOutput<'a, S, N> {
+    /// Create an output from the producer end of the transmit queue.
+    pub fn new(tx: Producer<'a, u8, N>) -> Self {
+        Self {
+            tx,
+            _serial: PhantomData,
+        }
+    }
+
+    /// Number of bytes that can be queued.
+    pub fn free_space(&self) -> usize {
+        self.tx.capacity() - self.tx.len()
+    }
+
+    /// Queue raw bytes. Returns false, queuing nothing, if there isn't room for all of them.
+    pub fn send_bytes(&mut self, bytes: &[u8]) -> bool {
+        if bytes.len() > self.free_space() {
+            return false;
+        }
+        for byte in bytes {
+            self.tx.enqueue(*byte).ok();
+        }
+        S::listen_tx(true);
+        true
+    }
+
+    /// Queue a message. Returns false if there isn't room for it.
+    pub fn send(&mut self, event: MidiEvent) -> bool {
+        self.send_bytes(&event.to_bytes())
+    }
+
+    /// Queue a SysEx message, `data` excludes the start and end bytes.
+    /// Returns false, queuing nothing, if there isn't room for it.
+    pub fn send_sysex(&mut self, data: &[u8]) -> bool {
+        if data.len() + 2 > self.free_space() {
+            return false;
+        }
+        self.tx.enqueue(0xF0).ok();
+        for byte in data {
+            self.tx.enqueue(*byte & 0x7F).ok();
+        }
+        self.tx.enqueue(0xF7).ok();
+        S::listen_tx(true);
+        true
+    }
+}
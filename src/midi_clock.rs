@@ -0,0 +1,221 @@
+//! Follows MIDI clock and transport messages from serial or USB MIDI, giving the tempo and
+//! position, e.g. to sync delays, LFOs or sequencers.
+//!
+//! Times are in microseconds from any free running source, they may wrap around.
+//!
+//! # Example
+//!
+//! ```rust
+//! let mut clock = MidiClock::new();
+//!
+//! while let Some(event) = input.read() {
+//!     match clock.handle(event, now_us()) {
+//!         Some(ClockEvent::Beat) => gate.set_high().unwrap(),
+//!         Some(ClockEvent::Stop) => gate.set_low().unwrap(),
+//!         _ => (),
+//!     }
+//! }
+//! if let Some(bpm) = clock.bpm(now_us()) {
+//!     delay.set_time(60.0 / bpm);
+//! }
+//! ```
+use crate::midi::MidiEvent;
+
+/// MIDI clock ticks per quarter note.
+pub const TICKS_PER_BEAT: u32 = 24;
+
+/// Without a tick for this long the clock is considered lost.
+pub const CLOCK_TIMEOUT_US: u32 = 500_000;
+
+/// Consecutive ticks that are far off the average before the tempo jumps to them.
+const OUTLIER_LIMIT: u8 = 3;
+
+/// Something that happened to the clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockEvent {
+    /// Playback started from the beginning.
+    Start,
+    /// Playback continued from the current position.
+    Continue,
+    Stop,
+    /// A tick while playing.
+    Tick,
+    /// A tick while playing, on a beat.
+    Beat,
+    /// The position was set by a song position pointer.
+    Position,
+}
+
+/// Tracks tempo, position and transport state from MIDI clock.
+pub struct MidiClock {
+    playing: bool,
+    //the next tick to play, and the current one which is the same until a tick is played
+    next_tick: u32,
+    tick: u32,
+    last_tick: Option<u32>,
+    interval: Option<f32>,
+    smoothing: f32,
+    outliers: u8,
+}
+
+impl Default for MidiClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiClock {
+    /// Create a stopped clock without a tempo.
+    pub fn new() -> Self {
+        Self {
+            playing: false,
+            next_tick: 0,
+            tick: 0,
+            last_tick: None,
+            interval: None,
+            smoothing: 0.1,
+            outliers: 0,
+        }
+    }
+
+    /// How strongly each tick changes the tempo, from 0.0 (never) to 1.0 (no smoothing).
+    /// Lower values reject more jitter but follow tempo changes more slowly.
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.clamp(0.0, 1.0);
+    }
+
+    /// Handle a MIDI event received at `now_us`, other events are ignored.
+    pub fn handle(&mut self, event: MidiEvent, now_us: u32) -> Option<ClockEvent> {
+        match event {
+            MidiEvent::Clock => self.tick(now_us),
+            MidiEvent::Start => {
+                self.playing = true;
+                self.set_position(0);
+                Some(ClockEvent::Start)
+            }
+            MidiEvent::Continue => {
+                self.playing = true;
+                Some(ClockEvent::Continue)
+            }
+            MidiEvent::Stop => {
+                self.playing = false;
+                Some(ClockEvent::Stop)
+            }
+            MidiEvent::SongPosition(sixteenths) if !self.playing => {
+                self.set_position(sixteenths as u32 * TICKS_PER_BEAT / 4);
+                Some(ClockEvent::Position)
+            }
+            _ => None,
+        }
+    }
+
+    fn tick(&mut self, now_us: u32) -> Option<ClockEvent> {
+        if let Some(last) = self.last_tick {
+            let dt = now_us.wrapping_sub(last);
+            if dt < CLOCK_TIMEOUT_US {
+                self.measure(dt as f32);
+            } else {
+                self.interval = None;
+            }
+        }
+        self.last_tick = Some(now_us);
+
+        if !self.playing {
+            return None;
+        }
+        // The first tick after start is the downbeat
+        self.tick = self.next_tick;
+        self.next_tick = self.next_tick.wrapping_add(1);
+        if self.tick_in_beat() == 0 {
+            Some(ClockEvent::Beat)
+        } else {
+            Some(ClockEvent::Tick)
+        }
+    }
+
+    fn set_position(&mut self, tick: u32) {
+        self.next_tick = tick;
+        self.tick = tick;
+    }
+
+    fn measure(&mut self, dt: f32) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => {
+                self.interval = Some(dt);
+                return;
+            }
+        };
+        //a late or early tick is jitter, several in a row are a tempo change
+        let error = if dt > interval {
+            dt - interval
+        } else {
+            interval - dt
+        };
+        if error > interval * 0.5 {
+            self.outliers += 1;
+            if self.outliers >= OUTLIER_LIMIT {
+                self.outliers = 0;
+                self.interval = Some(dt);
+            }
+            return;
+        }
+        self.outliers = 0;
+        self.interval = Some(interval + (dt - interval) * self.smoothing);
+    }
+
+    /// Returns true if ticks have been received recently.
+    pub fn has_clock(&self, now_us: u32) -> bool {
+        match self.last_tick {
+            Some(last) => now_us.wrapping_sub(last) < CLOCK_TIMEOUT_US,
+            None => false,
+        }
+    }
+
+    /// The smoothed tempo in beats per minute, if there is a clock.
+    pub fn bpm(&self, now_us: u32) -> Option<f32> {
+        if !self.has_clock(now_us) {
+            return None;
+        }
+        self.interval
+            .map(|interval| 60_000_000.0 / (interval * TICKS_PER_BEAT as f32))
+    }
+
+    /// The smoothed time between ticks in microseconds, if there is a clock.
+    pub fn tick_interval_us(&self, now_us: u32) -> Option<f32> {
+        self.bpm(now_us).and(self.interval)
+    }
+
+    /// Returns true while playing.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Ticks since the start of the song, counting the tick on the downbeat.
+    pub fn ticks(&self) -> u32 {
+        self.next_tick
+    }
+
+    /// The beat since the start of the song, counting from 0. Before the first tick after a
+    /// start or position, the beat that tick will be on.
+    pub fn beat(&self) -> u32 {
+        self.tick / TICKS_PER_BEAT
+    }
+
+    /// The tick within the current beat, from 0 to 23.
+    pub fn tick_in_beat(&self) -> u32 {
+        self.tick % TICKS_PER_BEAT
+    }
+
+    /// The position within the current beat from 0.0 to 1.0, interpolated between ticks.
+    pub fn phase(&self, now_us: u32) -> f32 {
+        let tick = self.tick_in_beat() as f32;
+        let fraction = match (self.last_tick, self.tick_interval_us(now_us)) {
+            (Some(last), Some(interval)) if self.playing && self.next_tick != self.tick => {
+                (now_us.wrapping_sub(last) as f32 / interval).min(1.0)
+            }
+            _ => 0.0,
+        };
+        (tick + fraction) / TICKS_PER_BEAT as f32
+    }
+}
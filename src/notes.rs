@@ -0,0 +1,295 @@
+//! Mapping keys to notes with voice allocation, e.g. to play a synth from the Field keyboard.
+use heapless::Vec;
+
+/// Most keys that can be tracked at once.
+pub const MAX_KEYS: usize = 32;
+
+/// A note event for a voice, like MIDI note on and off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteEvent {
+    NoteOn { voice: u8, note: u8, velocity: u8 },
+    NoteOff { voice: u8, note: u8 },
+}
+
+/// Scale used to map keys to notes, as semitones from the root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scale {
+    Chromatic,
+    Major,
+    Minor,
+    Dorian,
+    MajorPentatonic,
+    MinorPentatonic,
+    /// Semitones from the root within an octave, in ascending order.
+    Custom(&'static [u8]),
+}
+
+impl Scale {
+    /// Semitones from the root within an octave.
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Custom(intervals) => intervals,
+        }
+    }
+
+    /// The note for a scale degree above `root`, clamped to the MIDI range.
+    pub fn note(&self, root: u8, degree: usize) -> u8 {
+        let intervals = self.intervals();
+        if intervals.is_empty() {
+            return root;
+        }
+        let octave = (degree / intervals.len()) as u32;
+        let note = root as u32 + octave * 12 + intervals[degree % intervals.len()] as u32;
+        note.min(127) as u8
+    }
+}
+
+/// What happens when a note is played and all voices are in use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// The newest note sounds, stealing the voice of the oldest note.
+    Last,
+    /// The lowest notes sound.
+    Low,
+    /// The highest notes sound.
+    High,
+    /// Voices are used in turn, stealing the next voice if it is in use.
+    /// Released voices are not handed back to notes that are still held.
+    RoundRobin,
+}
+
+/// Assigns notes to `N` voices.
+pub struct VoiceAllocator<const N: usize> {
+    priority: Priority,
+    voices: [Option<u8>; N],
+    held: Vec<u8, MAX_KEYS>,
+    next: usize,
+    velocity: u8,
+}
+
+impl<const N: usize> VoiceAllocator<N> {
+    /// Create a new allocator with the given priority.
+    pub fn new(priority: Priority) -> Self {
+        Self {
+            priority,
+            voices: [None; N],
+            held: Vec::new(),
+            next: 0,
+            velocity: 100,
+        }
+    }
+
+    /// Set the priority.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    /// Set the velocity of note on events.
+    pub fn set_velocity(&mut self, velocity: u8) {
+        self.velocity = velocity.min(127);
+    }
+
+    /// The note playing on each voice.
+    pub fn voices(&self) -> &[Option<u8>; N] {
+        &self.voices
+    }
+
+    /// The notes held down, in the order they were played, including ones without a voice.
+    pub fn held(&self) -> &[u8] {
+        &self.held
+    }
+
+    fn voice_of(&self, note: u8) -> Option<usize> {
+        self.voices.iter().position(|v| *v == Some(note))
+    }
+
+    fn start<F: FnMut(NoteEvent)>(&mut self, voice: usize, note: u8, handler: &mut F) {
+        if let Some(old) = self.voices[voice] {
+            handler(NoteEvent::NoteOff {
+                voice: voice as u8,
+                note: old,
+            });
+        }
+        self.voices[voice] = Some(note);
+        self.next = (voice + 1) % N;
+        handler(NoteEvent::NoteOn {
+            voice: voice as u8,
+            note,
+            velocity: self.velocity,
+        });
+    }
+
+    // The voice a new note should take, or None if it shouldn't sound.
+    fn choose_voice(&self, note: u8) -> Option<usize> {
+        if N == 0 {
+            return None;
+        }
+        if self.priority == Priority::RoundRobin {
+            return (0..N)
+                .map(|i| (self.next + i) % N)
+                .find(|v| self.voices[*v].is_none())
+                .or(Some(self.next));
+        }
+        if let Some(free) = self.voices.iter().position(|v| v.is_none()) {
+            return Some(free);
+        }
+        let sounding = self
+            .voices
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.map(|n| (i, n)));
+        match self.priority {
+            Priority::Last => sounding
+                .min_by_key(|(_, n)| self.held.iter().position(|h| h == n))
+                .map(|(i, _)| i),
+            Priority::Low => sounding
+                .max_by_key(|(_, n)| *n)
+                .filter(|(_, n)| note < *n)
+                .map(|(i, _)| i),
+            Priority::High => sounding
+                .min_by_key(|(_, n)| *n)
+                .filter(|(_, n)| note > *n)
+                .map(|(i, _)| i),
+            Priority::RoundRobin => None,
+        }
+    }
+
+    /// Play a note, calling `handler` with the resulting events.
+    pub fn note_on<F: FnMut(NoteEvent)>(&mut self, note: u8, mut handler: F) {
+        if self.held.contains(&note) {
+            return;
+        }
+        if self.held.is_full() {
+            self.held.remove(0);
+        }
+        self.held.push(note).ok();
+        if let Some(voice) = self.choose_voice(note) {
+            self.start(voice, note, &mut handler);
+        }
+    }
+
+    /// Release a note, calling `handler` with the resulting events.
+    /// The freed voice is given to a held note without a voice, if there is one.
+    pub fn note_off<F: FnMut(NoteEvent)>(&mut self, note: u8, mut handler: F) {
+        self.held.retain(|h| *h != note);
+        let voice = match self.voice_of(note) {
+            Some(voice) => voice,
+            None => return,
+        };
+        self.voices[voice] = None;
+        handler(NoteEvent::NoteOff {
+            voice: voice as u8,
+            note,
+        });
+
+        let voices = &self.voices;
+        let mut waiting = self
+            .held
+            .iter()
+            .copied()
+            .filter(|h| !voices.contains(&Some(*h)));
+        let waiting = match self.priority {
+            Priority::Last => waiting.next_back(),
+            Priority::Low => waiting.min(),
+            Priority::High => waiting.max(),
+            Priority::RoundRobin => None,
+        };
+        if let Some(waiting) = waiting {
+            self.start(voice, waiting, &mut handler);
+        }
+    }
+
+    /// Release all notes.
+    pub fn all_off<F: FnMut(NoteEvent)>(&mut self, mut handler: F) {
+        self.held.clear();
+        for (voice, note) in self.voices.iter_mut().enumerate() {
+            if let Some(note) = note.take() {
+                handler(NoteEvent::NoteOff {
+                    voice: voice as u8,
+                    note,
+                });
+            }
+        }
+    }
+}
+
+/// Maps key indices to notes in a scale and plays them through a [VoiceAllocator].
+pub struct KeyNotes<const N: usize> {
+    allocator: VoiceAllocator<N>,
+    scale: Scale,
+    root: u8,
+    octave: i8,
+    playing: [Option<u8>; MAX_KEYS],
+}
+
+impl<const N: usize> KeyNotes<N> {
+    /// Create a new mapping, key 0 is `root` (a MIDI note number) in `scale`.
+    pub fn new(scale: Scale, root: u8, priority: Priority) -> Self {
+        Self {
+            allocator: VoiceAllocator::new(priority),
+            scale,
+            root: root.min(127),
+            octave: 0,
+            playing: [None; MAX_KEYS],
+        }
+    }
+
+    /// Set the scale, held keys keep their notes until released.
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    /// Set the root note.
+    pub fn set_root(&mut self, root: u8) {
+        self.root = root.min(127);
+    }
+
+    /// Set the octave offset from the root.
+    pub fn set_octave(&mut self, octave: i8) {
+        self.octave = octave;
+    }
+
+    /// Get the octave offset from the root.
+    pub fn octave(&self) -> i8 {
+        self.octave
+    }
+
+    /// The note for a key with the current scale, root and octave.
+    pub fn note(&self, key: usize) -> u8 {
+        let root = (self.root as i32 + self.octave as i32 * 12).clamp(0, 127) as u8;
+        self.scale.note(root, key)
+    }
+
+    /// Handle a key press, calling `handler` with the resulting events.
+    pub fn key_pressed<F: FnMut(NoteEvent)>(&mut self, key: usize, handler: F) {
+        assert!(key < MAX_KEYS);
+        if self.playing[key].is_some() {
+            return;
+        }
+        let note = self.note(key);
+        self.playing[key] = Some(note);
+        self.allocator.note_on(note, handler);
+    }
+
+    /// Handle a key release, calling `handler` with the resulting events.
+    pub fn key_released<F: FnMut(NoteEvent)>(&mut self, key: usize, handler: F) {
+        assert!(key < MAX_KEYS);
+        if let Some(note) = self.playing[key].take() {
+            // Another key may be mapped to the same note
+            if !self.playing.contains(&Some(note)) {
+                self.allocator.note_off(note, handler);
+            }
+        }
+    }
+
+    /// Get the voice allocator, e.g. to change the priority.
+    pub fn allocator(&mut self) -> &mut VoiceAllocator<N> {
+        &mut self.allocator
+    }
+}
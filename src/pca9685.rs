@@ -0,0 +1,332 @@
+//! PCA9685 16 channel, 12-bit PWM driver over I2C, for LEDs or servos.
+//!
+//! The driver buffers all channel values and doesn't own the bus, so several drivers can share
+//! it and the buffer can be sent by other means, e.g. interrupts, using [Pca9685::bytes].
+//!
+//! https://www.nxp.com/docs/en/data-sheet/PCA9685.pdf
+use stm32h7xx_hal::hal::blocking::i2c::Write;
+
+/// Address with all address pins low.
+pub const PCA9685_BASE_ADDR: u8 = 0b0100_0000;
+
+/// Number of PWM channels.
+pub const PCA9685_CHANNELS: usize = 16;
+
+/// Largest PWM value, the channel is fully on.
+pub const PCA9685_MAX: u16 = 0x0FFF;
+
+/// Frequency of the internal oscillator in Hz.
+pub const PCA9685_OSC_HZ: u32 = 25_000_000;
+
+const MODE1: u8 = 0x00;
+const LED0: u8 = 0x06; // location for start of LED0 registers
+const ALL_LED: u8 = 0xFA;
+const PRESCALE: u8 = 0xFE;
+
+const MODE1_SLEEP: u8 = 0b0001_0000;
+const MODE1_AUTO_INC: u8 = 0b0010_0000;
+const MODE1_EXTCLK: u8 = 0b0100_0000;
+
+const MODE2_INVRT: u8 = 0b0001_0000;
+const MODE2_OCH: u8 = 0b0000_1000;
+const MODE2_OUTDRV: u8 = 0b0000_0100;
+
+const FULL: u16 = 0x1000;
+
+/// Default servo pulse range in microseconds, see [Pca9685::set_servo].
+pub const SERVO_RANGE_US: (u32, u32) = (1_000, 2_000);
+
+/// Output state while OE is high.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputDisabled {
+    Low,
+    /// High with totem pole outputs, high impedance with open drain.
+    High,
+    HighImpedance,
+}
+
+/// Mode and frequency configuration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// Invert the outputs, e.g. for LEDs driven directly without a transistor.
+    pub invert: bool,
+    /// Totem pole (push-pull) outputs, otherwise open drain.
+    pub totem_pole: bool,
+    /// Outputs change on each ACK instead of on STOP.
+    pub change_on_ack: bool,
+    pub output_disabled: OutputDisabled,
+    /// PWM frequency in Hz (24Hz to 1526Hz with the internal oscillator).
+    /// `None` keeps the power on default of ~200Hz.
+    pub frequency: Option<u32>,
+    /// Frequency of an external clock on EXTCLK in Hz, instead of the internal oscillator.
+    pub external_clock: Option<u32>,
+}
+
+impl Default for Config {
+    /// Totem pole outputs at the default frequency, low when disabled.
+    fn default() -> Self {
+        Self {
+            invert: false,
+            totem_pole: true,
+            change_on_ack: false,
+            output_disabled: OutputDisabled::Low,
+            frequency: None,
+            external_clock: None,
+        }
+    }
+}
+
+impl Config {
+    /// Configuration for driving servos at 50Hz.
+    pub fn servo() -> Self {
+        Self {
+            frequency: Some(50),
+            ..Default::default()
+        }
+    }
+
+    fn mode2(&self) -> u8 {
+        let mut mode2 = match self.output_disabled {
+            OutputDisabled::Low => 0b00,
+            OutputDisabled::High => 0b01,
+            OutputDisabled::HighImpedance => 0b10,
+        };
+        if self.invert {
+            mode2 |= MODE2_INVRT;
+        }
+        if self.change_on_ack {
+            mode2 |= MODE2_OCH;
+        }
+        if self.totem_pole {
+            mode2 |= MODE2_OUTDRV;
+        }
+        mode2
+    }
+
+    fn osc_hz(&self) -> u32 {
+        self.external_clock.unwrap_or(PCA9685_OSC_HZ)
+    }
+
+    fn prescale(&self) -> Option<u8> {
+        self.frequency.map(|frequency| {
+            let prescale = (self.osc_hz() + 2048 * frequency) / (4096 * frequency.max(1));
+            prescale.saturating_sub(1).clamp(3, 255) as u8
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct Channel {
+    on: u16,
+    off: u16,
+}
+
+//struct representing an entire, auto incremented, update
+#[repr(C, packed)]
+struct TxBuffer {
+    reg: u8,
+    channels: [Channel; PCA9685_CHANNELS],
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        //full off
+        Self { on: 0, off: 0x1001 }
+    }
+}
+
+impl Default for TxBuffer {
+    fn default() -> Self {
+        let channels: [Channel; PCA9685_CHANNELS] = Default::default();
+        Self {
+            reg: LED0,
+            channels,
+        }
+    }
+}
+
+/// A PCA9685 with buffered channel values.
+pub struct Pca9685 {
+    addr: u8,
+    config: Config,
+    buffer: TxBuffer,
+    gamma: Option<&'static [u16; 256]>,
+    stagger: bool,
+    servo_range: (u32, u32),
+}
+
+impl Pca9685 {
+    /// Create a driver with the given address, it needs to be configured with
+    /// [Pca9685::configure] before use.
+    pub fn new(addr: u8, config: Config) -> Self {
+        Self {
+            addr,
+            config,
+            buffer: Default::default(),
+            gamma: None,
+            stagger: false,
+            servo_range: SERVO_RANGE_US,
+        }
+    }
+
+    /// The I2C address.
+    pub fn addr(&self) -> u8 {
+        self.addr
+    }
+
+    /// Get the configuration.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Set the configuration, applied by the next [Pca9685::configure].
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Write the mode registers and prescaler, and turn all channels off.
+    /// The buffered values are kept, send them with [Pca9685::write].
+    pub fn configure<I: Write + ?Sized>(&mut self, i2c: &mut I) -> Result<(), I::Error> {
+        let mut mode1 = MODE1_AUTO_INC;
+        if let Some(prescale) = self.config.prescale() {
+            //the prescaler can only be written while asleep
+            i2c.write(self.addr, &[MODE1, MODE1_SLEEP | MODE1_AUTO_INC])?;
+            if self.config.external_clock.is_some() {
+                mode1 |= MODE1_EXTCLK;
+                i2c.write(self.addr, &[MODE1, MODE1_SLEEP | mode1])?;
+            }
+            i2c.write(self.addr, &[PRESCALE, prescale])?;
+        }
+        i2c.write(self.addr, &[MODE1, mode1, self.config.mode2()])?;
+        //the oscillator takes 500us to start after sleep
+        if self.config.frequency.is_some() {
+            crate::delay_us(500);
+        }
+        //turn all, full off
+        i2c.write(self.addr, &[ALL_LED, 0, 0, 0, 0x10])
+    }
+
+    /// Put the oscillator to sleep, turning all outputs off, or wake it up.
+    pub fn set_sleep<I: Write + ?Sized>(
+        &mut self,
+        i2c: &mut I,
+        sleep: bool,
+    ) -> Result<(), I::Error> {
+        let mut mode1 = MODE1_AUTO_INC;
+        if self.config.external_clock.is_some() {
+            mode1 |= MODE1_EXTCLK;
+        }
+        if sleep {
+            mode1 |= MODE1_SLEEP;
+        }
+        i2c.write(self.addr, &[MODE1, mode1])
+    }
+
+    /// The PWM frequency in Hz, from the configured prescaler.
+    pub fn frequency(&self) -> f32 {
+        let prescale = self.config.prescale().unwrap_or(0x1E) as f32;
+        self.config.osc_hz() as f32 / (4096.0 * (prescale + 1.0))
+    }
+
+    /// Use a gamma table for [Pca9685::set_brightness], e.g. [GAMMA](crate::hid::GAMMA).
+    /// Brightness is linear without one.
+    pub fn set_gamma(&mut self, gamma: Option<&'static [u16; 256]>) {
+        self.gamma = gamma;
+    }
+
+    /// Offset the start of each channel's pulse, spreading the load on the supply.
+    pub fn set_stagger(&mut self, stagger: bool) {
+        self.stagger = stagger;
+    }
+
+    /// Set the buffered 12-bit value for a channel, [PCA9685_MAX] or above is fully on.
+    /// Returns true if the value changed.
+    pub fn set(&mut self, channel: usize, value: u16) -> bool {
+        assert!(channel < PCA9685_CHANNELS);
+        let on = if self.stagger {
+            (channel << 2) as u16 //offset on times
+        } else {
+            0
+        };
+        let (on, off) = if value == 0 {
+            //full off
+            (on, 0x1001 + on)
+        } else if value >= PCA9685_MAX {
+            (FULL | on, on)
+        } else {
+            (on, on.saturating_add(value) & PCA9685_MAX)
+        };
+
+        let channel: &mut Channel = &mut self.buffer.channels[channel];
+        if channel.on != on || channel.off != off {
+            channel.on = on;
+            channel.off = off;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Set the buffered 12-bit value for all channels. Returns true if any value changed.
+    pub fn set_all(&mut self, value: u16) -> bool {
+        let mut changed = false;
+        for channel in 0..PCA9685_CHANNELS {
+            changed |= self.set(channel, value);
+        }
+        changed
+    }
+
+    /// Convert a brightness to a value, through the gamma table if there is one.
+    pub fn brightness_value(&self, brightness: u8) -> u16 {
+        match self.gamma {
+            Some(gamma) => gamma[brightness as usize],
+            None => ((brightness as u16) << 4) | ((brightness as u16) >> 4),
+        }
+    }
+
+    /// Set the buffered brightness for a channel. Returns true if the value changed.
+    pub fn set_brightness(&mut self, channel: usize, brightness: u8) -> bool {
+        self.set(channel, self.brightness_value(brightness))
+    }
+
+    /// Set the buffered brightness for all channels. Returns true if any value changed.
+    pub fn set_brightness_all(&mut self, brightness: u8) -> bool {
+        self.set_all(self.brightness_value(brightness))
+    }
+
+    /// Set the buffered pulse length for a channel in microseconds, e.g. for a servo.
+    /// Returns true if the value changed.
+    pub fn set_pulse_us(&mut self, channel: usize, us: u32) -> bool {
+        let value = (us as f32 * self.frequency() * 4096.0 / 1_000_000.0) as u32;
+        self.set(channel, value.min(PCA9685_MAX as u32) as u16)
+    }
+
+    /// Set the servo pulse range in microseconds used by [Pca9685::set_servo].
+    pub fn set_servo_range(&mut self, min_us: u32, max_us: u32) {
+        self.servo_range = (min_us, max_us);
+    }
+
+    /// Set the buffered servo position for a channel, from 0.0 to 1.0.
+    /// Returns true if the value changed.
+    pub fn set_servo(&mut self, channel: usize, position: f32) -> bool {
+        let (min, max) = self.servo_range;
+        let us = min as f32 + (max as f32 - min as f32) * position.clamp(0.0, 1.0);
+        self.set_pulse_us(channel, us as u32)
+    }
+
+    /// The buffered update, starting with the register address, for sending by other means.
+    pub fn bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                &self.buffer as *const TxBuffer as *const u8,
+                core::mem::size_of::<TxBuffer>(),
+            )
+        }
+    }
+
+    /// Send the buffered values for all channels.
+    pub fn write<I: Write + ?Sized>(&self, i2c: &mut I) -> Result<(), I::Error> {
+        i2c.write(self.addr, self.bytes())
+    }
+}
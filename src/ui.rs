@@ -0,0 +1,498 @@
+//! Small widget toolkit for monochrome displays like the Field's OLED.
+//!
+//! Widgets draw into any embedded-graphics [DrawTarget] with [BinaryColor] and are driven by
+//! [Action]s, which a [Navigation] creates from [HidEvent]s.
+//!
+//! # Example
+//!
+//! ```rust
+//! let mut nav = ui::Navigation::encoder(ENCODER_ID, SWITCH_ID);
+//! let mut menu = ui::List::new(&["Tune", "Filter", "Envelope"]).with_title("Synth");
+//!
+//! while let Some(event) = consumer.dequeue() {
+//!     if let Some(action) = nav.action(&event) {
+//!         if let Some(index) = menu.handle(action) {
+//!             popup.show("Selected", 1_000);
+//!         }
+//!     }
+//! }
+//! menu.draw(&mut display, display.bounding_box()).unwrap();
+//! popup.draw(&mut display).unwrap();
+//! display.flush().unwrap();
+//! ```
+use core::fmt::Write;
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_5X8, ascii::FONT_6X10, MonoFont, MonoTextStyle, MonoTextStyleBuilder},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use heapless::String;
+use micromath::F32Ext;
+
+use crate::hid::HidEvent;
+
+/// Font for lists and popups.
+pub const FONT: MonoFont<'static> = FONT_6X10;
+
+/// Smaller font for labels.
+pub const SMALL_FONT: MonoFont<'static> = FONT_5X8;
+
+/// Longest popup message.
+pub const POPUP_LEN: usize = 32;
+
+/// A user interface action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Move by a number of steps, positive is down or clockwise.
+    Move(i32),
+    Select,
+    Back,
+}
+
+/// Maps [HidEvent]s from controls with the given ids to [Action]s.
+/// Without a back switch, a long press of select is back and select happens on release.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Navigation {
+    encoder: Option<u8>,
+    previous: Option<u8>,
+    next: Option<u8>,
+    select: Option<u8>,
+    back: Option<u8>,
+    long_pressed: bool,
+}
+
+impl Navigation {
+    /// Navigate with an encoder and its push switch.
+    pub fn encoder(encoder: u8, select: u8) -> Self {
+        Self {
+            encoder: Some(encoder),
+            previous: None,
+            next: None,
+            select: Some(select),
+            back: None,
+            long_pressed: false,
+        }
+    }
+
+    /// Navigate with previous, next and select switches.
+    pub fn buttons(previous: u8, next: u8, select: u8) -> Self {
+        Self {
+            encoder: None,
+            previous: Some(previous),
+            next: Some(next),
+            select: Some(select),
+            back: None,
+            long_pressed: false,
+        }
+    }
+
+    /// Use a separate back switch.
+    pub fn with_back(mut self, back: u8) -> Self {
+        self.back = Some(back);
+        self
+    }
+
+    /// Get the action for an event, if any.
+    pub fn action(&mut self, event: &HidEvent) -> Option<Action> {
+        let long_back = self.back.is_none();
+        match *event {
+            HidEvent::Encoder(id, steps) if Some(id) == self.encoder && steps != 0 => {
+                Some(Action::Move(steps))
+            }
+            HidEvent::Pressed(id) | HidEvent::Repeat(id) if Some(id) == self.previous => {
+                Some(Action::Move(-1))
+            }
+            HidEvent::Pressed(id) | HidEvent::Repeat(id) if Some(id) == self.next => {
+                Some(Action::Move(1))
+            }
+            HidEvent::Pressed(id) if Some(id) == self.back => Some(Action::Back),
+            HidEvent::Pressed(id) if Some(id) == self.select => {
+                self.long_pressed = false;
+                if long_back {
+                    None
+                } else {
+                    Some(Action::Select)
+                }
+            }
+            HidEvent::LongPress(id) if long_back && Some(id) == self.select => {
+                self.long_pressed = true;
+                Some(Action::Back)
+            }
+            HidEvent::Released(id) if long_back && Some(id) == self.select => {
+                if core::mem::replace(&mut self.long_pressed, false) {
+                    None
+                } else {
+                    Some(Action::Select)
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn text_style(
+    font: &'static MonoFont<'static>,
+    inverted: bool,
+) -> MonoTextStyle<'static, BinaryColor> {
+    let (text, background) = if inverted {
+        (BinaryColor::Off, BinaryColor::On)
+    } else {
+        (BinaryColor::On, BinaryColor::Off)
+    };
+    MonoTextStyleBuilder::new()
+        .font(font)
+        .text_color(text)
+        .background_color(background)
+        .build()
+}
+
+/// A scrolling list of items with one selected, e.g. a menu.
+pub struct List<'a> {
+    items: &'a [&'a str],
+    title: Option<&'a str>,
+    selected: usize,
+    top: usize,
+    wrap: bool,
+}
+
+impl<'a> List<'a> {
+    /// Create a new list with the first item selected.
+    pub fn new(items: &'a [&'a str]) -> Self {
+        Self {
+            items,
+            title: None,
+            selected: 0,
+            top: 0,
+            wrap: false,
+        }
+    }
+
+    /// Draw a title above the items.
+    pub fn with_title(mut self, title: &'a str) -> Self {
+        self.title = Some(title);
+        self
+    }
+
+    /// Wrap around from the last item to the first and the other way.
+    pub fn with_wrap(mut self, wrap: bool) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// The selected index.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Set the selected index.
+    pub fn set_selected(&mut self, index: usize) {
+        self.selected = index.min(self.items.len().saturating_sub(1));
+    }
+
+    /// Handle an action, returns the selected index when it is chosen.
+    pub fn handle(&mut self, action: Action) -> Option<usize> {
+        let len = self.items.len() as i32;
+        match action {
+            Action::Move(steps) if len > 0 => {
+                let index = self.selected as i32 + steps;
+                self.selected = if self.wrap {
+                    index.rem_euclid(len)
+                } else {
+                    index.clamp(0, len - 1)
+                } as usize;
+                None
+            }
+            Action::Select if len > 0 => Some(self.selected),
+            _ => None,
+        }
+    }
+
+    /// Draw into `area`, scrolling to keep the selected item visible.
+    pub fn draw<D>(&mut self, target: &mut D, area: Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let row = FONT.character_size.height as i32;
+        let mut y = area.top_left.y;
+        area.into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+            .draw(target)?;
+
+        if let Some(title) = self.title {
+            Text::with_baseline(
+                title,
+                Point::new(area.top_left.x, y),
+                text_style(&FONT, false),
+                Baseline::Top,
+            )
+            .draw(target)?;
+            y += row;
+            Rectangle::new(
+                Point::new(area.top_left.x, y),
+                Size::new(area.size.width, 1),
+            )
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(target)?;
+            y += 2;
+        }
+
+        let rows = ((area.top_left.y + area.size.height as i32 - y) / row).max(1) as usize;
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + rows {
+            self.top = self.selected + 1 - rows;
+        }
+
+        for (index, item) in self.items.iter().enumerate().skip(self.top).take(rows) {
+            let selected = index == self.selected;
+            if selected {
+                Rectangle::new(
+                    Point::new(area.top_left.x, y),
+                    Size::new(area.size.width, row as u32),
+                )
+                .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                .draw(target)?;
+            }
+            Text::with_baseline(
+                item,
+                Point::new(area.top_left.x + 1, y),
+                text_style(&FONT, selected),
+                Baseline::Top,
+            )
+            .draw(target)?;
+            y += row;
+        }
+        Ok(())
+    }
+}
+
+/// A horizontal bar showing a value from 0.0 to 1.0 with an optional label.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueBar<'a> {
+    pub label: Option<&'a str>,
+    pub value: f32,
+    /// Fill from the middle, for values centered on 0.5.
+    pub bipolar: bool,
+}
+
+impl<'a> ValueBar<'a> {
+    /// Create a new bar.
+    pub fn new(label: Option<&'a str>, value: f32) -> Self {
+        Self {
+            label,
+            value,
+            bipolar: false,
+        }
+    }
+
+    /// Change the value by `step` for each step of a move action, returns true if it changed.
+    pub fn handle(&mut self, action: Action, step: f32) -> bool {
+        match action {
+            Action::Move(steps) => {
+                let value = (self.value + steps as f32 * step).clamp(0.0, 1.0);
+                let changed = (value - self.value).abs() > 0.0;
+                self.value = value;
+                changed
+            }
+            _ => false,
+        }
+    }
+
+    /// Draw into `area`, the label takes the left half if there is one.
+    pub fn draw<D>(&self, target: &mut D, area: Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        area.into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+            .draw(target)?;
+        let mut bar = area;
+        if let Some(label) = self.label {
+            Text::with_baseline(
+                label,
+                area.top_left,
+                text_style(&SMALL_FONT, false),
+                Baseline::Top,
+            )
+            .draw(target)?;
+            let half = area.size.width / 2;
+            bar.top_left.x += half as i32;
+            bar.size.width -= half;
+        }
+        bar.into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(target)?;
+
+        let inner = bar.size.width.saturating_sub(4) as f32;
+        let value = self.value.clamp(0.0, 1.0);
+        let (start, end) = if self.bipolar {
+            let middle = inner * 0.5;
+            let position = inner * value;
+            (middle.min(position), middle.max(position))
+        } else {
+            (0.0, inner * value)
+        };
+        let width = (end - start) as u32;
+        if width > 0 {
+            Rectangle::new(
+                bar.top_left + Point::new(2 + start as i32, 2),
+                Size::new(width, bar.size.height.saturating_sub(4)),
+            )
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Draw a label and value bar for each knob, in a grid with `columns` columns.
+///
+/// # Example
+///
+/// ```rust
+/// // The Field's 8 knobs in 2 rows
+/// let mut values = [0.0; FIELD_KNOB_COUNT];
+/// for (i, value) in values.iter_mut().enumerate() {
+///     *value = controls.knob(i).get_value();
+/// }
+/// ui::draw_knob_labels(&mut display, display.bounding_box(), &LABELS, &values, 4).unwrap();
+/// ```
+pub fn draw_knob_labels<D>(
+    target: &mut D,
+    area: Rectangle,
+    labels: &[&str],
+    values: &[f32],
+    columns: usize,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let columns = columns.max(1);
+    let rows = (labels.len() + columns - 1) / columns;
+    if rows == 0 {
+        return Ok(());
+    }
+    let width = area.size.width / columns as u32;
+    let height = area.size.height / rows as u32;
+    let text_height = SMALL_FONT.character_size.height;
+
+    for (index, (label, value)) in labels.iter().zip(values.iter()).enumerate() {
+        let top_left = area.top_left
+            + Point::new(
+                ((index % columns) as u32 * width) as i32,
+                ((index / columns) as u32 * height) as i32,
+            );
+        let cell = Rectangle::new(top_left, Size::new(width, height));
+        cell.into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+            .draw(target)?;
+        Text::with_baseline(
+            label,
+            top_left,
+            text_style(&SMALL_FONT, false),
+            Baseline::Top,
+        )
+        .draw(target)?;
+        let bar = Rectangle::new(
+            top_left + Point::new(0, text_height as i32 + 1),
+            Size::new(
+                width.saturating_sub(2),
+                height.saturating_sub(text_height + 2).min(6),
+            ),
+        );
+        ValueBar::new(None, *value).draw(target, bar)?;
+    }
+    Ok(())
+}
+
+/// A message drawn over the display for a while, e.g. to show a value being changed.
+pub struct Popup {
+    text: String<POPUP_LEN>,
+    remaining: u32,
+}
+
+impl Default for Popup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Popup {
+    /// Create a hidden popup.
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            remaining: 0,
+        }
+    }
+
+    /// Show `text` for `duration` calls to [Popup::tick], longer text is truncated.
+    pub fn show(&mut self, text: &str, duration: u32) {
+        self.text.clear();
+        for c in text.chars() {
+            if self.text.push(c).is_err() {
+                break;
+            }
+        }
+        self.remaining = duration;
+    }
+
+    /// Show formatted text, e.g. `popup.show_fmt(format_args!("Cutoff {}", value), 500)`.
+    pub fn show_fmt(&mut self, args: core::fmt::Arguments, duration: u32) {
+        self.text.clear();
+        // Truncated if too long
+        let _ = self.text.write_fmt(args);
+        self.remaining = duration;
+    }
+
+    /// Hide the popup.
+    pub fn hide(&mut self) {
+        self.remaining = 0;
+    }
+
+    /// Count down the time left, returns true when the popup was just hidden so the display
+    /// can be redrawn.
+    pub fn tick(&mut self) -> bool {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.remaining == 0
+        } else {
+            false
+        }
+    }
+
+    /// If the popup is showing.
+    pub fn is_visible(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// Draw centered on the display if showing.
+    pub fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        if !self.is_visible() {
+            return Ok(());
+        }
+        let bounds = target.bounding_box();
+        let char_size = FONT.character_size;
+        let width = (self.text.len() as u32 * char_size.width + 8).min(bounds.size.width);
+        let height = char_size.height + 8;
+        let top_left = bounds.center() - Point::new(width as i32 / 2, height as i32 / 2);
+        let frame = Rectangle::new(top_left, Size::new(width, height));
+
+        frame
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+            .draw(target)?;
+        frame
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(target)?;
+        Text::with_baseline(
+            &self.text,
+            top_left + Point::new(4, 4),
+            text_style(&FONT, false),
+            Baseline::Top,
+        )
+        .draw(target)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+//! USB MIDI device on the Seed's USB port, sending and receiving the same [MidiEvent]s as
+//! serial MIDI.
+//!
+//! Needs the `usb-midi` feature.
+//!
+//! # Example
+//!
+//! ```rust
+//! // In init, before taking peripherals out of `ccdr.peripheral`
+//! usb_midi::enable_clock(&mut ccdr);
+//! let mut usb = UsbMidi::new(
+//!     device.OTG2_HS_GLOBAL,
+//!     device.OTG2_HS_DEVICE,
+//!     device.OTG2_HS_PWRCLK,
+//!     gpioa.pa10,
+//!     gpioa.pa11,
+//!     gpioa.pa12,
+//!     ccdr.peripheral.USB2OTG,
+//!     &ccdr.clocks,
+//!     "daisy midi",
+//! );
+//!
+//! // In a task bound to OTG_FS
+//! usb.poll();
+//! while let Some(event) = usb.read() {
+//!     usb.send(event).ok();
+//! }
+//! ```
+use heapless::spsc::Queue;
+use stm32h7xx_hal::{
+    gpio::{gpioa, Analog},
+    rcc::{self, rec, rec::UsbClkSel},
+    stm32,
+    usb_hs::{UsbBus, USB2},
+};
+use usb_device::{class_prelude::UsbBusAllocator, prelude::*};
+use usbd_midi::{
+    data::usb::constants::{USB_AUDIO_CLASS, USB_MIDISTREAMING_SUBCLASS},
+    midi_device::MidiClass,
+};
+
+use crate::midi::{MidiEvent, MidiParser};
+
+pub use usb_device::UsbError;
+
+/// USB vendor and product id, from the shared pid.codes range for testing.
+pub const USB_VID_PID: (u16, u16) = (0x16c0, 0x5e4);
+
+/// Number of received events that can wait to be read.
+pub const USB_MIDI_QUEUE_LEN: usize = 64;
+
+const PACKET_SIZE: usize = 4;
+const CABLE: u8 = 0;
+
+// Code index numbers, the first byte of each packet after the cable
+const CIN_SYSCOMMON_2: u8 = 0x2;
+const CIN_SYSCOMMON_3: u8 = 0x3;
+const CIN_SYSEX: u8 = 0x4;
+const CIN_SYSEX_END_1: u8 = 0x5;
+const CIN_SINGLE_BYTE: u8 = 0xF;
+
+static mut EP_MEMORY: [u32; 1024] = [0; 1024];
+
+/// USB bus of the Seed's USB port.
+pub type UsbMidiBus = UsbBus<USB2>;
+
+/// Select the HSI48 clock for USB, it needs to be done before [UsbMidi::new].
+pub fn enable_clock(ccdr: &mut rcc::Ccdr) {
+    let _ = ccdr.clocks.hsi48_ck().expect("HSI48 must run");
+    ccdr.peripheral.kernel_usb_clk_mux(UsbClkSel::HSI48);
+}
+
+// Number of MIDI bytes in a packet with this code index number
+fn packet_len(cin: u8) -> usize {
+    match cin {
+        0x5 | 0xC | 0xD | 0xF => 1,
+        0x2 | 0x6 => 2,
+        0x3 | 0x4 | 0x7 | 0x8..=0xB | 0xE => 3,
+        //reserved
+        _ => 0,
+    }
+}
+
+/// A class compliant USB MIDI device with one input and one output.
+pub struct UsbMidi {
+    device: UsbDevice<'static, UsbMidiBus>,
+    midi: MidiClass<'static, UsbMidiBus>,
+    parser: MidiParser,
+    events: Queue<MidiEvent, USB_MIDI_QUEUE_LEN>,
+    dropped: u32,
+}
+
+impl UsbMidi {
+    /// Set up the USB port as a MIDI device, named `product`.
+    ///
+    /// # Panics
+    /// Will panic if done more than once.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        otg_global: stm32::OTG2_HS_GLOBAL,
+        otg_device: stm32::OTG2_HS_DEVICE,
+        otg_pwrclk: stm32::OTG2_HS_PWRCLK,
+        usb_id: gpioa::PA10<Analog>,
+        usb_dm: gpioa::PA11<Analog>,
+        usb_dp: gpioa::PA12<Analog>,
+        prec: rec::Usb2Otg,
+        clocks: &rcc::CoreClocks,
+        product: &'static str,
+    ) -> Self {
+        //float makes this a device
+        usb_id.into_floating_input();
+        let usb = USB2::new(
+            otg_global,
+            otg_device,
+            otg_pwrclk,
+            usb_dm.into_alternate_af10(),
+            usb_dp.into_alternate_af10(),
+            prec,
+            clocks,
+        );
+
+        let usb_bus = cortex_m::singleton!(
+            : UsbBusAllocator<UsbMidiBus> = UsbBus::new(usb, unsafe { &mut EP_MEMORY })
+        )
+        .unwrap();
+
+        let midi = MidiClass::new(usb_bus, 1, 1).unwrap();
+        let device = UsbDeviceBuilder::new(usb_bus, UsbVidPid(USB_VID_PID.0, USB_VID_PID.1))
+            .product(product)
+            .device_class(USB_AUDIO_CLASS)
+            .device_sub_class(USB_MIDISTREAMING_SUBCLASS)
+            .build();
+
+        Self {
+            device,
+            midi,
+            parser: MidiParser::new(),
+            events: Queue::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Handle USB events and receive MIDI, call from the OTG_FS interrupt.
+    /// Returns true if events were received.
+    pub fn poll(&mut self) -> bool {
+        if !self.device.poll(&mut [&mut self.midi]) {
+            return false;
+        }
+
+        let mut buffer = [0; 64];
+        let size = match self.midi.read(&mut buffer) {
+            Ok(size) => size,
+            Err(_) => return false,
+        };
+        let mut received = false;
+        for packet in buffer[..size].chunks_exact(PACKET_SIZE) {
+            let len = packet_len(packet[0] & 0x0F);
+            for byte in &packet[1..1 + len] {
+                if let Some(event) = self.parser.parse(*byte) {
+                    received = true;
+                    if self.events.enqueue(event).is_err() {
+                        self.dropped = self.dropped.wrapping_add(1);
+                    }
+                }
+            }
+        }
+        received
+    }
+
+    /// Get the next received event.
+    pub fn read(&mut self) -> Option<MidiEvent> {
+        self.events.dequeue()
+    }
+
+    /// Number of received events lost because they weren't read in time.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Get the parser, e.g. for [MidiParser::sysex].
+    pub fn parser(&self) -> &MidiParser {
+        &self.parser
+    }
+
+    /// Returns true once the host has configured the device.
+    pub fn is_configured(&self) -> bool {
+        self.device.state() == UsbDeviceState::Configured
+    }
+
+    fn send_packet(&mut self, cin: u8, bytes: &[u8]) -> Result<(), UsbError> {
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = (CABLE << 4) | cin;
+        packet[1..1 + bytes.len()].copy_from_slice(bytes);
+        self.midi.send_bytes(packet).map(|_| ())
+    }
+
+    /// Send a message, without waiting. [MidiEvent::SysEx] is ignored, see
+    /// [UsbMidi::send_sysex].
+    pub fn send(&mut self, event: MidiEvent) -> Result<(), UsbError> {
+        let bytes = event.to_bytes();
+        let cin = match bytes.first() {
+            Some(status) if *status < 0xF0 => status >> 4,
+            Some(_) => match bytes.len() {
+                1 => CIN_SINGLE_BYTE,
+                2 => CIN_SYSCOMMON_2,
+                _ => CIN_SYSCOMMON_3,
+            },
+            None => return Ok(()),
+        };
+        self.send_packet(cin, &bytes)
+    }
+
+    /// Send a SysEx message, `data` excludes the start and end bytes.
+    /// Stops at the first packet that can't be sent.
+    pub fn send_sysex(&mut self, data: &[u8]) -> Result<(), UsbError> {
+        let mut message = core::iter::once(0xF0)
+            .chain(data.iter().map(|b| b & 0x7F))
+            .chain(core::iter::once(0xF7))
+            .peekable();
+        let mut bytes = [0; 3];
+        while message.peek().is_some() {
+            let mut len = 0;
+            while len < bytes.len() {
+                match message.next() {
+                    Some(byte) => {
+                        bytes[len] = byte;
+                        len += 1;
+                    }
+                    None => break,
+                }
+            }
+            let cin = if bytes[len - 1] == 0xF7 {
+                CIN_SYSEX_END_1 + len as u8 - 1
+            } else {
+                CIN_SYSEX
+            };
+            self.send_packet(cin, &bytes[..len])?;
+        }
+        Ok(())
+    }
+}